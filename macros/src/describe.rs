@@ -0,0 +1,204 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+
+use libsqlite3_sys as ffi;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// What a query takes and returns, as found out from a local copy of the
+// database and cached in .spin-sqlx for offline builds.  That's apart from
+// sqlx's own .sqlx, as `cargo sqlx prepare` owns that and its format differs.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct QueryData {
+    pub query: String,
+    pub parameters: usize,
+    pub columns: Vec<ColumnData>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ColumnData {
+    pub name: String,
+    // As written in the CREATE TABLE; None for expressions
+    pub declared_type: Option<String>,
+    // None if we couldn't trace the column to a table
+    pub nullable: Option<bool>,
+}
+
+// The environment variables query_data reads, which the build must be
+// rerun for if they change
+pub const TRACKED_ENV: &[&str] = &["DATABASE_URL", "SQLX_OFFLINE", "SPIN_SQLITE_DB_PATH"];
+
+// Mirrors sqlx: with DATABASE_URL set (and SQLX_OFFLINE not) we describe the
+// query against the database and refresh the cached copy in .spin-sqlx;
+// otherwise we use the cached copy.  Also returns the files the result came
+// from, which the build must be rerun for if they change.
+pub fn query_data(sql: &str) -> Result<(QueryData, Vec<PathBuf>), String> {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").map_err(|_| "CARGO_MANIFEST_DIR must be set")?);
+    let offline = std::env::var("SQLX_OFFLINE")
+        .map(|s| s.eq_ignore_ascii_case("true") || s == "1")
+        .unwrap_or(false);
+    let cache_file = manifest_dir.join(".spin-sqlx").join(format!("query-{}.json", hash_query(sql)));
+
+    match std::env::var("DATABASE_URL") {
+        Ok(url) if !offline => {
+            let path = database_path(&url, &manifest_dir)?;
+            let data = describe(&path, sql)?;
+            write_cache(&cache_file, &data)?;
+            Ok((data, vec![path, cache_file]))
+        },
+        _ => {
+            let data = read_cache(&cache_file, sql)?;
+            Ok((data, vec![cache_file]))
+        },
+    }
+}
+
+fn hash_query(sql: &str) -> String {
+    hex::encode(Sha256::digest(sql.as_bytes()))
+}
+
+fn read_cache(cache_file: &Path, sql: &str) -> Result<QueryData, String> {
+    let text = std::fs::read_to_string(cache_file).map_err(|_| {
+        "`DATABASE_URL` must be set to a spin-sqlite:// URL, or .spin-sqlx must contain \
+         data for this query from a previous build with `DATABASE_URL` set".to_owned()
+    })?;
+    let data: QueryData = serde_json::from_str(&text)
+        .map_err(|e| format!("invalid query data in {}: {e}", cache_file.display()))?;
+    if data.query != sql {
+        return Err(format!("query data in {} is for a different query", cache_file.display()));
+    }
+    Ok(data)
+}
+
+// Leaves the file alone if it already says the same, as it's an input to
+// the build too: rewriting it would have cargo (and `spin watch`) rebuild
+fn write_cache(cache_file: &Path, data: &QueryData) -> Result<(), String> {
+    let write = || -> std::io::Result<()> {
+        let text = serde_json::to_string_pretty(data)?;
+        if matches!(std::fs::read_to_string(cache_file), Ok(old) if old == text) {
+            return Ok(());
+        }
+        if let Some(dir) = cache_file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(cache_file, text)
+    };
+    write().map_err(|e| format!("failed to write {}: {e}", cache_file.display()))
+}
+
+// spin-sqlite://<label> maps to the file Spin uses for that label when
+// running locally.  Spin keeps the default database in .spin; other labels
+// are placed by runtime config, which we don't read, so those need
+// SPIN_SQLITE_DB_PATH to say where the file is.
+fn database_path(url: &str, manifest_dir: &Path) -> Result<PathBuf, String> {
+    let label = url.strip_prefix("spin-sqlite:")
+        .ok_or_else(|| format!("`DATABASE_URL` {url:?} is not a spin-sqlite URL"))?;
+    let label = label.trim_start_matches("//");
+    let label = label.split('?').next().unwrap_or_default();
+
+    let path = match std::env::var("SPIN_SQLITE_DB_PATH") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) if label == "default" => PathBuf::from(".spin/sqlite_db.db"),
+        Err(_) => return Err(format!("set SPIN_SQLITE_DB_PATH to the file for database {label:?}")),
+    };
+    Ok(manifest_dir.join(path))
+}
+
+fn describe(path: &Path, sql: &str) -> Result<QueryData, String> {
+    let db = Db::open(path)?;
+    let stmt = db.prepare(sql)?;
+
+    unsafe {
+        let parameters = ffi::sqlite3_bind_parameter_count(stmt.0) as usize;
+
+        let mut columns = vec![];
+        for i in 0..ffi::sqlite3_column_count(stmt.0) {
+            let name = from_c(ffi::sqlite3_column_name(stmt.0, i)).unwrap_or_default();
+            let declared_type = from_c(ffi::sqlite3_column_decltype(stmt.0, i));
+
+            let database = ffi::sqlite3_column_database_name(stmt.0, i);
+            let table = ffi::sqlite3_column_table_name(stmt.0, i);
+            let origin = ffi::sqlite3_column_origin_name(stmt.0, i);
+            let nullable = if database.is_null() || table.is_null() || origin.is_null() {
+                None
+            } else {
+                db.column_nullable(database, table, origin)
+            };
+
+            columns.push(ColumnData { name, declared_type, nullable });
+        }
+
+        Ok(QueryData { query: sql.to_owned(), parameters, columns })
+    }
+}
+
+struct Db(*mut ffi::sqlite3);
+struct Stmt(*mut ffi::sqlite3_stmt);
+
+impl Db {
+    fn open(path: &Path) -> Result<Self, String> {
+        let c_path = CString::new(path.to_string_lossy().as_bytes()).map_err(|e| e.to_string())?;
+        let mut handle = std::ptr::null_mut();
+        let rc = unsafe { ffi::sqlite3_open_v2(c_path.as_ptr(), &mut handle, ffi::SQLITE_OPEN_READONLY, std::ptr::null()) };
+        let db = Self(handle);
+        if rc != ffi::SQLITE_OK {
+            return Err(format!("failed to open {}: {}", path.display(), db.error_message()));
+        }
+        Ok(db)
+    }
+
+    fn prepare(&self, sql: &str) -> Result<Stmt, String> {
+        let c_sql = CString::new(sql).map_err(|e| e.to_string())?;
+        let mut handle = std::ptr::null_mut();
+        let rc = unsafe { ffi::sqlite3_prepare_v2(self.0, c_sql.as_ptr(), -1, &mut handle, std::ptr::null_mut()) };
+        if rc != ffi::SQLITE_OK {
+            return Err(self.error_message());
+        }
+        Ok(Stmt(handle))
+    }
+
+    // An INTEGER PRIMARY KEY is the rowid and can't be NULL even though
+    // it's not declared NOT NULL.  This can't see outer joins, so columns
+    // from the right of a LEFT JOIN need a `?` override.
+    unsafe fn column_nullable(&self, database: *const c_char, table: *const c_char, column: *const c_char) -> Option<bool> {
+        let mut declared_type = std::ptr::null();
+        let mut collation = std::ptr::null();
+        let mut not_null = 0;
+        let mut primary_key = 0;
+        let mut autoincrement = 0;
+        let rc = ffi::sqlite3_table_column_metadata(
+            self.0, database, table, column,
+            &mut declared_type, &mut collation, &mut not_null, &mut primary_key, &mut autoincrement,
+        );
+        if rc != ffi::SQLITE_OK {
+            return None;
+        }
+        let is_rowid = primary_key != 0 && from_c(declared_type).map(|t| t.eq_ignore_ascii_case("INTEGER")).unwrap_or(false);
+        Some(not_null == 0 && !is_rowid)
+    }
+
+    fn error_message(&self) -> String {
+        unsafe { from_c(ffi::sqlite3_errmsg(self.0)) }.unwrap_or_else(|| "unknown SQLite error".to_owned())
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        unsafe { ffi::sqlite3_close(self.0) };
+    }
+}
+
+impl Drop for Stmt {
+    fn drop(&mut self) {
+        unsafe { ffi::sqlite3_finalize(self.0) };
+    }
+}
+
+unsafe fn from_c(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(s).to_string_lossy().into_owned())
+    }
+}
@@ -0,0 +1,259 @@
+//! Compile-time checked `query!` and `query_as!` for the Spin SQLite sqlx
+//! driver.  sqlx's own macros only know about the drivers built into sqlx,
+//! so these stand in for them.
+//!
+//! Point `DATABASE_URL` at `spin-sqlite://default` to check queries against
+//! the database Spin uses locally (`.spin/sqlite_db.db`); set
+//! `SPIN_SQLITE_DB_PATH` to use a different file.  Each build with
+//! `DATABASE_URL` set saves what it learns in `.spin-sqlx`, and builds
+//! without it (or with `SQLX_OFFLINE=true`) use that instead.  (Not `.sqlx`,
+//! which belongs to `cargo sqlx prepare`.)
+//!
+//! The generated code expects the driver at `crate::spin_sqlx`.
+
+use std::path::PathBuf;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Expr, Ident, LitStr, Token, Type};
+
+mod describe;
+
+use describe::{ColumnData, QueryData};
+
+struct QueryInput {
+    sql: LitStr,
+    args: Vec<Expr>,
+}
+
+impl Parse for QueryInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let sql = input.parse()?;
+        let args = if input.is_empty() {
+            vec![]
+        } else {
+            input.parse::<Token![,]>()?;
+            Punctuated::<Expr, Token![,]>::parse_terminated(input)?.into_iter().collect()
+        };
+        Ok(Self { sql, args })
+    }
+}
+
+struct QueryAsInput {
+    record: Type,
+    query: QueryInput,
+}
+
+impl Parse for QueryAsInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let record = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let query = input.parse()?;
+        Ok(Self { record, query })
+    }
+}
+
+/// Checks a query against the database and returns rows as an anonymous
+/// record with a field per column.
+///
+/// Column types come from the table definitions.  For expressions, or to
+/// override what was inferred, name the column `"name: Type"`; add `!` or `?`
+/// to the name to force it to be non-null or nullable.
+///
+/// ```ignore
+/// let pets = spin_sqlx_macros::query!("SELECT name, COUNT(*) AS \"count: u32\" FROM pets2 WHERE age < ?", 7)
+///     .fetch_all(&conn)
+///     .await?;
+/// ```
+#[proc_macro]
+pub fn query(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as QueryInput);
+    expand_query(input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Checks a query against the database and maps each row to `T` by setting
+/// the field with the same name as each column.
+///
+/// Each field must be convertible (`From`) from its column's type, as
+/// `query!` would infer it, so a mismatch is a compile error.  Columns whose
+/// type can't be inferred are checked when the row is decoded instead.
+///
+/// ```ignore
+/// let people = spin_sqlx_macros::query_as!(Person, "SELECT name FROM test WHERE name = ?", "honk")
+///     .fetch_all(&conn)
+///     .await?;
+/// ```
+#[proc_macro]
+pub fn query_as(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as QueryAsInput);
+    expand_query_as(input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+fn expand_query(input: QueryInput) -> syn::Result<TokenStream> {
+    let (data, inputs) = checked_query_data(&input)?;
+
+    let mut fields = vec![];
+    let mut types = vec![];
+    for column in &data.columns {
+        let override_ = ColumnOverride::parse(&column.name);
+        fields.push(field_ident(override_.name, &input.sql)?);
+        let ty = column_type(column, &override_, &input.sql)?.ok_or_else(|| syn::Error::new(
+            input.sql.span(),
+            format!("can't tell the type of column {:?}; name it like \"{}: Type\"", override_.name, override_.name),
+        ))?;
+        types.push(ty);
+    }
+    let indexes = 0..fields.len();
+
+    let query = bound_query(&input);
+    let rebuild_inputs = rebuild_inputs(&inputs);
+    Ok(quote! {{
+        #rebuild_inputs
+
+        #[derive(Debug)]
+        struct Record {
+            #( #fields: #types, )*
+        }
+
+        #query.try_map(|row: crate::spin_sqlx::SpinSqliteRow| {
+            use ::sqlx::Row as _;
+            ::std::result::Result::Ok(Record {
+                #( #fields: row.try_get::<#types, _>(#indexes)?, )*
+            })
+        })
+    }})
+}
+
+fn expand_query_as(input: QueryAsInput) -> syn::Result<TokenStream> {
+    let (data, inputs) = checked_query_data(&input.query)?;
+
+    let mut fields = vec![];
+    let mut values = vec![];
+    for (index, column) in data.columns.iter().enumerate() {
+        let override_ = ColumnOverride::parse(&column.name);
+        fields.push(field_ident(override_.name, &input.query.sql)?);
+        // As sqlx does: the field must come from the column's type, which
+        // checks it at compile time
+        values.push(match column_type(column, &override_, &input.query.sql)? {
+            Some(ty) => quote! { row.try_get::<#ty, _>(#index)?.into() },
+            None => quote! { row.try_get(#index)? },
+        });
+    }
+    let record = &input.record;
+
+    let query = bound_query(&input.query);
+    let rebuild_inputs = rebuild_inputs(&inputs);
+    Ok(quote! {{
+        #rebuild_inputs
+
+        #query.try_map(|row: crate::spin_sqlx::SpinSqliteRow| {
+            use ::sqlx::Row as _;
+            ::std::result::Result::Ok(#record {
+                #( #fields: #values, )*
+            })
+        })
+    }})
+}
+
+fn checked_query_data(input: &QueryInput) -> syn::Result<(QueryData, Vec<PathBuf>)> {
+    let (data, inputs) = describe::query_data(&input.sql.value())
+        .map_err(|e| syn::Error::new(input.sql.span(), e))?;
+    if data.parameters != input.args.len() {
+        return Err(syn::Error::new(
+            input.sql.span(),
+            format!("expected {} parameters, got {}", data.parameters, input.args.len()),
+        ));
+    }
+    Ok((data, inputs))
+}
+
+// Proc macros can't declare their inputs on stable, but rustc reruns a build
+// when a file it include_bytes! or a variable it env! reads changes, so we
+// have the generated code do that
+fn rebuild_inputs(files: &[PathBuf]) -> TokenStream {
+    let files = files.iter().map(|f| f.to_string_lossy().into_owned());
+    let vars = describe::TRACKED_ENV;
+    quote! {
+        #( const _: &[u8] = ::std::include_bytes!(#files); )*
+        #( const _: ::std::option::Option<&str> = ::std::option_env!(#vars); )*
+    }
+}
+
+fn bound_query(input: &QueryInput) -> TokenStream {
+    let sql = &input.sql;
+    let args = &input.args;
+    quote! {
+        ::sqlx::query::<crate::spin_sqlx::SqlxConnection>(#sql)
+            #( .bind(#args) )*
+    }
+}
+
+// sqlx's convention for column overrides: `name: Type`, `name!`, `name?`
+struct ColumnOverride<'a> {
+    name: &'a str,
+    ty: Option<&'a str>,
+    nullable: Option<bool>,
+}
+
+impl<'a> ColumnOverride<'a> {
+    fn parse(column: &'a str) -> Self {
+        let (name, ty) = match column.split_once(':') {
+            Some((name, ty)) => (name.trim(), Some(ty.trim())),
+            None => (column, None),
+        };
+        if let Some(name) = name.strip_suffix('!') {
+            Self { name, ty, nullable: Some(false) }
+        } else if let Some(name) = name.strip_suffix('?') {
+            Self { name, ty, nullable: Some(true) }
+        } else {
+            Self { name, ty, nullable: None }
+        }
+    }
+}
+
+fn field_ident(name: &str, sql: &LitStr) -> syn::Result<Ident> {
+    syn::parse_str::<Ident>(name)
+        .map_err(|_| syn::Error::new(sql.span(), format!("column name {name:?} is not a valid Rust identifier; use AS to rename it")))
+}
+
+// None if there's no override and the column isn't from a table
+fn column_type(column: &ColumnData, override_: &ColumnOverride, sql: &LitStr) -> syn::Result<Option<TokenStream>> {
+    let ty = match override_.ty {
+        Some(ty) => syn::parse_str::<Type>(ty)
+            .map(|t| quote! { #t })
+            .map_err(|_| syn::Error::new(sql.span(), format!("invalid type {ty:?} for column {:?}", override_.name)))?,
+        None => match &column.declared_type {
+            Some(declared_type) => type_for_declared_type(declared_type),
+            None => return Ok(None),
+        },
+    };
+
+    // Unknown nullability is treated as nullable, to be safe
+    let nullable = override_.nullable.or(column.nullable).unwrap_or(true);
+    Ok(Some(if nullable {
+        quote! { ::std::option::Option<#ty> }
+    } else {
+        ty
+    }))
+}
+
+// Per SQLite's affinity rules (https://www.sqlite.org/datatype3.html), except
+// that we take BOOLEAN to mean bool
+fn type_for_declared_type(declared_type: &str) -> TokenStream {
+    let declared_type = declared_type.to_ascii_uppercase();
+    if declared_type.contains("BOOL") {
+        quote! { bool }
+    } else if declared_type.contains("INT") {
+        quote! { i64 }
+    } else if declared_type.contains("CHAR") || declared_type.contains("CLOB") || declared_type.contains("TEXT") {
+        quote! { ::std::string::String }
+    } else if declared_type.contains("BLOB") || declared_type.is_empty() {
+        quote! { ::std::vec::Vec<u8> }
+    } else if declared_type.contains("REAL") || declared_type.contains("FLOA") || declared_type.contains("DOUB") {
+        quote! { f64 }
+    } else {
+        quote! { i64 }
+    }
+}
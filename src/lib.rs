@@ -78,11 +78,8 @@ async fn handle_sqlxtest(_req: Request) -> anyhow::Result<impl IntoResponse> {
     //     .execute(&sqlx_conn)
     //     .await?;
 
-    // We can't do anything with QR - SQLite does not return rows affected or
-    // any such jollity
-
     // let rs = sqlx::query_as::<_, Person>("SELECT name FROM test")
-    // // let rs = sqlx::query_as!(Person2, "SELECT name FROM test")  // cargo sqlx prepare complains "no database driver found matching URL scheme"
+    // // let rs = spin_sqlx_macros::query_as!(Person2, "SELECT name FROM test")  // needs the macros feature, and DATABASE_URL=spin-sqlite://default, or .spin-sqlx from a build that had it
     //     .fetch(&sqlx_conn);
     // use futures::stream::StreamExt;
     // //let strs = rs.map(|r| r.unwrap().name);
@@ -1,9 +1,22 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 
 // use sqlx::Row;
 use sqlx::ColumnIndex;
 
 mod convert;
+mod describe;
+mod error;
+#[cfg(test)]
+mod tests;
+
+// Outside Spin there's no host to talk to, so tests swap in a fake
+#[cfg(not(test))]
+use spin_sdk::sqlite::Connection as HostConnection;
+#[cfg(test)]
+use tests::FakeHost as HostConnection;
+
+pub use error::{SpinSqliteAmbiguousColumnError, SpinSqliteDatabaseError, SpinSqliteDecodeError};
 
 impl ColumnIndex<SpinSqliteRow> for usize {
     fn index(&self, container: &SpinSqliteRow) -> Result<usize, sqlx::Error> {
@@ -17,32 +30,414 @@ impl ColumnIndex<SpinSqliteRow> for usize {
 
 impl ColumnIndex<SpinSqliteRow> for &str {
     fn index(&self, container: &SpinSqliteRow) -> Result<usize, sqlx::Error> {
-        container.columns.iter().position(|c| c == self)
-            .ok_or_else(|| sqlx::Error::ColumnNotFound(self.to_string()))
+        container.columns.index_of(self)
     }
 }
 
 
 #[derive(Debug)]
-pub struct SqlxConnection(spin_sdk::sqlite::Connection);
+pub struct SqlxConnection {
+    inner: HostConnection,
+    // Number of transactions currently open on this connection
+    transaction_depth: usize,
+    transaction_mode: TransactionMode,
+    // Set by begin_with for the duration of a single begin
+    next_transaction_mode: Option<TransactionMode>,
+    busy_retries: u32,
+    lenient_decoding: bool,
+    case_insensitive_column_names: bool,
+    log_settings: LogSettings,
+    // As of the last statement whose changes we counted, for reporting
+    // against statements that can't insert
+    last_insert_rowid: std::sync::atomic::AtomicI64,
+}
 
 impl SqlxConnection {
-    pub fn new(conn: spin_sdk::sqlite::Connection) -> Self {
-        Self(conn)
+    pub fn new(conn: HostConnection) -> Self {
+        Self {
+            inner: conn,
+            transaction_depth: 0,
+            transaction_mode: TransactionMode::default(),
+            next_transaction_mode: None,
+            busy_retries: 0,
+            lenient_decoding: false,
+            case_insensitive_column_names: false,
+            log_settings: LogSettings::default(),
+            last_insert_rowid: std::sync::atomic::AtomicI64::new(0),
+        }
     }
 
     pub fn open(label: &str) -> anyhow::Result<Self> {
-        Ok(Self(spin_sdk::sqlite::Connection::open(label)?))
+        Ok(Self::new(HostConnection::open(label)?))
     }
 
     pub fn open_default() -> anyhow::Result<Self> {
-        Ok(Self(spin_sdk::sqlite::Connection::open_default()?))
+        Ok(Self::new(HostConnection::open_default()?))
+    }
+
+    /// Sets the locking behaviour for transactions started by `begin`.
+    /// Nested transactions are savepoints and are not affected.
+    pub fn set_transaction_mode(&mut self, mode: TransactionMode) {
+        self.transaction_mode = mode;
+    }
+
+    /// Begins a transaction with the given locking behaviour, overriding the
+    /// connection's transaction mode for this transaction only.
+    ///
+    /// Fails if a transaction is already open, since a nested transaction is
+    /// a savepoint and SQLite doesn't let savepoints choose their locking.
+    pub fn begin_with(&mut self, mode: TransactionMode) -> BoxFuture<'_, Result<sqlx::Transaction<'_, SqlxConnection>, sqlx::Error>> {
+        Box::pin(async move {
+            if self.transaction_depth > 0 {
+                return Err(config_error(format!("can't begin a nested transaction with mode {mode:?}; it would be a savepoint, which has no mode")));
+            }
+            // Set only once polled, and taken by TransactionManager::begin
+            // within this same poll, so it can't outlive this begin
+            self.next_transaction_mode = Some(mode);
+            sqlx::Transaction::begin(self).await
+        })
+    }
+
+    /// Sets how many times `transaction_with_retry` re-runs a transaction
+    /// that failed because the database was busy or locked.
+    pub fn set_busy_retries(&mut self, retries: u32) {
+        self.busy_retries = retries;
+    }
+
+    /// Sets whether rows from this connection decode values the way SQLite's
+    /// type affinity would convert them: text such as `'42'` as a number,
+    /// and `'true'`/`'false'`/`'t'`/`'f'` as a bool.  Off by default, where a
+    /// value must already be of the type asked for, except that integers
+    /// always decode as `f32`/`f64` if they convert exactly (as upstream's
+    /// SQLite driver allows, and as expressions like `SUM` need).
+    pub fn set_lenient_decoding(&mut self, lenient: bool) {
+        self.lenient_decoding = lenient;
+    }
+
+    /// Sets whether rows from this connection find columns by name ignoring
+    /// ASCII case, as SQLite does with names in SQL.  An exact match is
+    /// still preferred.  Off by default.
+    pub fn set_case_insensitive_column_names(&mut self, case_insensitive: bool) {
+        self.case_insensitive_column_names = case_insensitive;
+    }
+
+    // Splits a host result set into rows which share a single set of columns
+    fn rows_from(&self, rs: spin_sdk::sqlite::QueryResult) -> impl Iterator<Item = SpinSqliteRow> {
+        let columns = SpinSqliteColumn::from_query_result(&rs);
+        let columns = std::sync::Arc::new(ResultColumns::new(columns, self.case_insensitive_column_names));
+        let lenient = self.lenient_decoding;
+        rs.rows.into_iter()
+            .map(move |r| SpinSqliteRow { columns: columns.clone(), inner: r, lenient })
+    }
+
+    /// Runs `callback` inside a transaction, committing if it returns `Ok`
+    /// and rolling back if it returns `Err`.  If the transaction fails because
+    /// the database is busy or locked, the whole thing (including `callback`)
+    /// is retried, up to the connection's `busy_retries` times.
+    pub fn transaction_with_retry<'a, F, R>(&'a mut self, mut callback: F) -> BoxFuture<'a, Result<R, sqlx::Error>>
+    where
+        for<'c> F: FnMut(&'c mut sqlx::Transaction<'_, SqlxConnection>) -> BoxFuture<'c, Result<R, sqlx::Error>>
+            + 'a
+            + Send,
+        R: Send,
+    {
+        Box::pin(async move {
+            let mut retries_left = self.busy_retries;
+            loop {
+                match run_transaction(&mut *self, &mut callback).await {
+                    Err(e) if retries_left > 0 && is_busy(&e) => retries_left -= 1,
+                    result => return result,
+                }
+            }
+        })
+    }
+
+    fn execute_query(&self, sql: &str, args: &[spin_sdk::sqlite::Value]) -> Result<spin_sdk::sqlite::QueryResult, sqlx::Error> {
+        self.inner.execute(sql, args)
+            .map_err(|e| sqlx::Error::from(SpinSqliteDatabaseError::from(e)))
+    }
+
+    // For statements that come in through the Executor, which get logged
+    // as the options say
+    fn execute_logged(&self, sql: &str, args: &[spin_sdk::sqlite::Value]) -> Result<spin_sdk::sqlite::QueryResult, sqlx::Error> {
+        let start = std::time::Instant::now();
+        let res = self.execute_query(sql, args);
+        let rows = res.as_ref().ok().map(|rs| rs.rows.len());
+        self.log_settings.log(sql, args.len(), rows, start.elapsed());
+        res
+    }
+
+    // The host doesn't tell us what a statement changed, so we have to ask
+    // SQLite afterwards.  changes() is left over from the last INSERT, UPDATE
+    // or DELETE, so we only trust it if total_changes() moved.
+    fn execute_counted(&self, sql: &str, args: &[spin_sdk::sqlite::Value]) -> Result<(spin_sdk::sqlite::QueryResult, SpinSqliteQR), sqlx::Error> {
+        // Queries can't change anything, so there's nothing to ask about
+        if is_read_only(sql) {
+            let rs = self.execute_logged(sql, args)?;
+            let last_insert_rowid = self.last_insert_rowid.load(std::sync::atomic::Ordering::Relaxed);
+            return Ok((rs, SpinSqliteQR { rows_affected: 0, last_insert_rowid }));
+        }
+
+        let total_before = self.total_changes()?;
+        let rs = self.execute_logged(sql, args)?;
+        let counts = self.execute_sql("SELECT changes(), total_changes(), last_insert_rowid()")?;
+        let (changes, total_after, last_insert_rowid) = match counts.rows.first().map(|r| r.values.as_slice()) {
+            Some([
+                spin_sdk::sqlite::Value::Integer(changes),
+                spin_sdk::sqlite::Value::Integer(total_after),
+                spin_sdk::sqlite::Value::Integer(last_insert_rowid),
+            ]) => (*changes, *total_after, *last_insert_rowid),
+            _ => return Err(sqlx::Error::Protocol("unexpected result reading change counts".to_owned())),
+        };
+        let rows_affected = if total_after != total_before { changes.try_into().unwrap_or(0) } else { 0 };
+        self.last_insert_rowid.store(last_insert_rowid, std::sync::atomic::Ordering::Relaxed);
+        Ok((rs, SpinSqliteQR { rows_affected, last_insert_rowid }))
+    }
+
+    fn total_changes(&self) -> Result<i64, sqlx::Error> {
+        let rs = self.execute_sql("SELECT total_changes()")?;
+        match rs.rows.first().and_then(|r| r.values.first()) {
+            Some(spin_sdk::sqlite::Value::Integer(n)) => Ok(*n),
+            _ => Err(sqlx::Error::Protocol("unexpected result reading change counts".to_owned())),
+        }
+    }
+
+    // For statements we issue ourselves (transaction control and suchlike)
+    // rather than ones that come in through the Executor
+    fn execute_sql(&self, sql: &str) -> Result<spin_sdk::sqlite::QueryResult, sqlx::Error> {
+        self.execute_query(sql, &[])
+    }
+}
+
+// Statements which can only read.  WITH isn't here as it can lead into an
+// INSERT, UPDATE or DELETE.
+fn is_read_only(sql: &str) -> bool {
+    let first_word = sql.split_whitespace().next().unwrap_or_default().to_ascii_uppercase();
+    matches!(first_word.as_str(), "SELECT" | "VALUES" | "EXPLAIN")
+}
+
+async fn run_transaction<F, R>(conn: &mut SqlxConnection, callback: &mut F) -> Result<R, sqlx::Error>
+where
+    for<'c> F: FnMut(&'c mut sqlx::Transaction<'_, SqlxConnection>) -> BoxFuture<'c, Result<R, sqlx::Error>>,
+{
+    let mut tx = sqlx::Connection::begin(conn).await?;
+    match callback(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            // The callback's error is the one to report (and maybe retry),
+            // so failing to roll back is only logged.  Dropping the
+            // transaction tries again.
+            if let Err(rollback_error) = tx.rollback().await {
+                log::warn!("failed to roll back after {e}: {rollback_error}");
+            }
+            Err(e)
+        }
     }
 }
 
+fn is_busy(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(e) => e.try_downcast_ref::<SpinSqliteDatabaseError>()
+            .map(|e| e.is_busy())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Options for opening a `SqlxConnection`, e.g. from a `spin-sqlite://<label>`
+/// URL.
+///
+/// Note that although these satisfy what `sqlx::Pool` asks of a database,
+/// the pool itself needs sqlx's tokio or async-std runtime for timeouts and
+/// for returning connections, and neither is available in a Spin component.
+/// Open a connection per request with `connect` instead.
 #[derive(Clone, Debug)]
 pub struct SqlxConnectionOptions {
     label: String,
+    transaction_mode: TransactionMode,
+    busy_retries: u32,
+    read_only: bool,
+    // None leaves it at the host's default
+    foreign_keys: Option<bool>,
+    lenient_decoding: bool,
+    case_insensitive_column_names: bool,
+    log_settings: LogSettings,
+}
+
+// Defaults are the same as the drivers built into sqlx
+#[derive(Clone, Debug)]
+struct LogSettings {
+    statements_level: LevelFilter,
+    slow_statements_level: LevelFilter,
+    slow_statements_duration: std::time::Duration,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            statements_level: LevelFilter::Debug,
+            slow_statements_level: LevelFilter::Warn,
+            slow_statements_duration: std::time::Duration::from_secs(1),
+        }
+    }
+}
+
+impl LogSettings {
+    // `rows` is None if the statement failed
+    fn log(&self, sql: &str, args: usize, rows: Option<usize>, elapsed: std::time::Duration) {
+        let slow = self.slow_statements_level != LevelFilter::Off && elapsed >= self.slow_statements_duration;
+        let level = if slow { self.slow_statements_level } else { self.statements_level };
+        let Some(level) = level.to_level() else {
+            return;
+        };
+
+        let rows = match rows {
+            Some(rows) => rows.to_string(),
+            None => "failed".to_owned(),
+        };
+        let slow = if slow { "slow statement: " } else { "" };
+        log::log!(
+            target: "sqlx::query",
+            level,
+            "{slow}args: {args}, rows returned: {rows}, elapsed: {elapsed:.3?}\n\n{sql}\n"
+        );
+    }
+}
+
+impl SqlxConnectionOptions {
+    fn with_label(label: String) -> Self {
+        Self {
+            label,
+            transaction_mode: TransactionMode::default(),
+            busy_retries: 0,
+            read_only: false,
+            foreign_keys: None,
+            lenient_decoding: false,
+            case_insensitive_column_names: false,
+            log_settings: LogSettings::default(),
+        }
+    }
+
+    /// The URL for these options, which `from_url` turns back into the same
+    /// options.  Lossy because the log settings aren't part of it.
+    pub fn to_url_lossy(&self) -> url::Url {
+        // spin-sqlite://<label> where the label makes a plain host, and
+        // spin-sqlite:<percent-encoded label> where it doesn't
+        let plain_host = !self.label.is_empty()
+            && self.label.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+        let url = if plain_host {
+            format!("spin-sqlite://{}", self.label)
+        } else {
+            format!("spin-sqlite:{}", utf8_percent_encode(&self.label, LABEL_ENCODE_SET))
+        };
+        let mut url = url::Url::parse(&url).expect("label should be encoded into a valid URL");
+
+        let mut params = vec![];
+        if self.read_only {
+            params.push(("mode", "ro".to_owned()));
+        }
+        if let Some(foreign_keys) = self.foreign_keys {
+            params.push(("foreign_keys", foreign_keys.to_string()));
+        }
+        if self.busy_retries > 0 {
+            params.push(("busy_retries", self.busy_retries.to_string()));
+        }
+        if self.transaction_mode != TransactionMode::default() {
+            params.push(("transaction_mode", self.transaction_mode.url_param().to_owned()));
+        }
+        if self.lenient_decoding {
+            params.push(("lenient_decoding", "true".to_owned()));
+        }
+        if self.case_insensitive_column_names {
+            params.push(("case_insensitive_column_names", "true".to_owned()));
+        }
+        if !params.is_empty() {
+            url.query_pairs_mut().extend_pairs(params);
+        }
+
+        url
+    }
+
+    /// Opens the database read-only (`?mode=ro`).
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Turns enforcement of foreign key constraints on or off
+    /// (`?foreign_keys=true`).
+    pub fn foreign_keys(mut self, on: bool) -> Self {
+        self.foreign_keys = Some(on);
+        self
+    }
+
+    pub fn transaction_mode(mut self, mode: TransactionMode) -> Self {
+        self.transaction_mode = mode;
+        self
+    }
+
+    pub fn busy_retries(mut self, retries: u32) -> Self {
+        self.busy_retries = retries;
+        self
+    }
+
+    /// Decodes values by SQLite's affinity rules rather than strictly
+    /// (`?lenient_decoding=true`).  See `SqlxConnection::set_lenient_decoding`.
+    pub fn lenient_decoding(mut self, lenient: bool) -> Self {
+        self.lenient_decoding = lenient;
+        self
+    }
+
+    /// Finds columns by name ignoring ASCII case
+    /// (`?case_insensitive_column_names=true`).  See
+    /// `SqlxConnection::set_case_insensitive_column_names`.
+    pub fn case_insensitive_column_names(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive_column_names = case_insensitive;
+        self
+    }
+}
+
+/// How SQLite acquires locks for a transaction.  See
+/// https://www.sqlite.org/lang_transaction.html.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TransactionMode {
+    /// Take locks only when the database is first read or written.
+    #[default]
+    Deferred,
+    /// Take the write lock immediately.
+    Immediate,
+    /// Take the write lock immediately and keep out readers too.
+    Exclusive,
+}
+
+impl TransactionMode {
+    fn begin_sql(&self) -> &'static str {
+        match self {
+            Self::Deferred => "BEGIN DEFERRED",
+            Self::Immediate => "BEGIN IMMEDIATE",
+            Self::Exclusive => "BEGIN EXCLUSIVE",
+        }
+    }
+
+    fn url_param(&self) -> &'static str {
+        match self {
+            Self::Deferred => "deferred",
+            Self::Immediate => "immediate",
+            Self::Exclusive => "exclusive",
+        }
+    }
+
+    fn from_url_param(value: &str) -> Option<Self> {
+        match value {
+            "deferred" => Some(Self::Deferred),
+            "immediate" => Some(Self::Immediate),
+            "exclusive" => Some(Self::Exclusive),
+            _ => None,
+        }
+    }
 }
 
 impl sqlx::Connection for SqlxConnection {
@@ -50,21 +445,32 @@ impl sqlx::Connection for SqlxConnection {
 
     type Options = SqlxConnectionOptions;
 
+    // Dropping the host connection closes it (and SQLite rolls back anything
+    // left open)
     fn close(self) -> BoxFuture<'static, Result<(), sqlx::Error>> {
-        Box::pin(async move { Ok(()) })
+        Box::pin(async move {
+            drop(self);
+            Ok(())
+        })
     }
     fn close_hard(self) -> BoxFuture<'static, Result<(), sqlx::Error>> {
-        Box::pin(async move { Ok(()) })
+        Box::pin(async move {
+            drop(self);
+            Ok(())
+        })
     }
 
     fn ping(&mut self) -> BoxFuture<'_, Result<(), sqlx::Error>> {
-        Box::pin(async move { Ok(()) })
+        Box::pin(async move {
+            self.execute_sql("SELECT 1")?;
+            Ok(())
+        })
     }
 
     fn begin(&mut self) -> BoxFuture<'_, Result<sqlx::Transaction<'_, Self::Database>, sqlx::Error>>
     where
         Self: Sized {
-        todo!()
+        sqlx::Transaction::begin(self)
     }
 
     fn shrink_buffers(&mut self) {
@@ -96,18 +502,81 @@ impl sqlx::Database for SqlxConnection {
     const URL_SCHEMES: &'static [&'static str] = &["spin-sqlite"];
 }
 
+sqlx_core::impl_encode_for_option!(SqlxConnection);
+
 pub struct SpinSqliteRow {
-    columns: std::sync::Arc<Vec<String>>,
+    columns: std::sync::Arc<ResultColumns>,
     inner: spin_sdk::sqlite::RowResult,
+    lenient: bool,
 }
-#[derive(Default)]
+
+// The columns of a result set, which its rows share, with an index for
+// finding them by name
+struct ResultColumns {
+    columns: Vec<SpinSqliteColumn>,
+    // Several ordinals if the query returned duplicate names, e.g. the ids
+    // from SELECT * FROM a JOIN b
+    by_name: HashMap<String, Vec<usize>>,
+    // Keyed by ASCII lowercase name; only built if asked for
+    by_folded_name: Option<HashMap<String, Vec<usize>>>,
+}
+
+impl ResultColumns {
+    fn new(columns: Vec<SpinSqliteColumn>, case_insensitive: bool) -> Self {
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::with_capacity(columns.len());
+        let mut by_folded_name: Option<HashMap<String, Vec<usize>>> = case_insensitive.then(HashMap::new);
+        for column in &columns {
+            by_name.entry(column.name.clone()).or_default().push(column.ordinal);
+            if let Some(by_folded_name) = &mut by_folded_name {
+                by_folded_name.entry(column.name.to_ascii_lowercase()).or_default().push(column.ordinal);
+            }
+        }
+        Self { columns, by_name, by_folded_name }
+    }
+
+    fn index_of(&self, name: &str) -> Result<usize, sqlx::Error> {
+        let ordinals = self.by_name.get(name)
+            .or_else(|| self.by_folded_name.as_ref()?.get(&name.to_ascii_lowercase()))
+            .ok_or_else(|| sqlx::Error::ColumnNotFound(name.to_owned()))?;
+        match ordinals.as_slice() {
+            [ordinal] => Ok(*ordinal),
+            _ => Err(sqlx::Error::ColumnDecode {
+                index: format!("{name:?}"),
+                source: Box::new(SpinSqliteAmbiguousColumnError::new(name, ordinals.clone())),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct SpinSqliteQR {
-    // inner: Option<spin_sdk::sqlite::QueryResult>,  // Option because we can't construct a default one
+    rows_affected: u64,
+    last_insert_rowid: i64,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SpinSqliteColumn {
+    ordinal: usize,
+    name: String,
+    type_info: SpinSqliteTypeInfo,
+}
 
+impl SpinSqliteColumn {
+    // The host doesn't tell us declared column types, so go by what's in the
+    // first row.  This is what SQLite itself reports for expression columns.
+    fn from_query_result(rs: &spin_sdk::sqlite::QueryResult) -> Vec<Self> {
+        let first_row = rs.rows.first();
+        rs.columns.iter()
+            .enumerate()
+            .map(|(ordinal, name)| {
+                let type_info = first_row
+                    .and_then(|r| r.values.get(ordinal))
+                    .map(SpinSqliteTypeInfo::from)
+                    .unwrap_or(SpinSqliteTypeInfo::Null);
+                Self { ordinal, name: name.clone(), type_info }
+            })
+            .collect()
+    }
 }
 #[derive(Clone, Debug, PartialEq)]
 pub enum SpinSqliteTypeInfo {
@@ -119,14 +588,15 @@ pub enum SpinSqliteTypeInfo {
 }
 
 pub struct SpinSqliteValue {
-    // inner: spin_sdk::sqlite::Value,
+    inner: spin_sdk::sqlite::Value,
+    lenient: bool,
 }
 
 impl sqlx::Row for SpinSqliteRow {
     type Database = SqlxConnection;
 
     fn columns(&self) -> &[<Self::Database as sqlx::Database>::Column] {
-        todo!()
+        &self.columns.columns
     }
 
     fn try_get_raw<I>(
@@ -141,49 +611,88 @@ impl sqlx::Row for SpinSqliteRow {
             return Err(sqlx::Error::ColumnIndexOutOfBounds { index: uindex, len: self.inner.values.len() });
         }
 
-        let val = &self.inner.values[uindex];
-        Ok(SpinSqliteValueRef { inner: val.clone() })
+        Ok(SpinSqliteValueRef { inner: &self.inner.values[uindex], lenient: self.lenient })
+    }
+
+    // As the default, but naming the column in errors rather than giving
+    // whatever index the caller used
+    fn try_get<'r, T, I>(&'r self, index: I) -> Result<T, sqlx::Error>
+    where
+        I: sqlx::ColumnIndex<Self>,
+        T: sqlx::Decode<'r, Self::Database> + sqlx::Type<Self::Database> {
+        use sqlx::{TypeInfo, ValueRef};
+
+        let ordinal = index.index(self)?;
+        let value = self.try_get_raw(ordinal)?;
+
+        // Lenient decoding takes values of other types, so leaves it to
+        // Decode to say what it can't use
+        if !value.is_null() && !value.lenient {
+            let ty = value.type_info();
+            if !ty.is_null() && !T::compatible(&ty) {
+                let source = SpinSqliteDecodeError::wrong_type(std::any::type_name::<T>(), value.inner);
+                return Err(self.column_decode_error(ordinal, Box::new(source)));
+            }
+        }
+
+        T::decode(value).map_err(|source| self.column_decode_error(ordinal, source))
+    }
+
+    fn try_get_unchecked<'r, T, I>(&'r self, index: I) -> Result<T, sqlx::Error>
+    where
+        I: sqlx::ColumnIndex<Self>,
+        T: sqlx::Decode<'r, Self::Database> {
+        let ordinal = index.index(self)?;
+        let value = self.try_get_raw(ordinal)?;
+        T::decode(value).map_err(|source| self.column_decode_error(ordinal, source))
+    }
+}
+
+impl SpinSqliteRow {
+    fn column_decode_error(&self, ordinal: usize, source: sqlx::error::BoxDynError) -> sqlx::Error {
+        let index = match self.columns.columns.get(ordinal) {
+            Some(column) => format!("{:?} (index {ordinal})", column.name),
+            None => ordinal.to_string(),
+        };
+        sqlx::Error::ColumnDecode { index, source }
     }
 }
 
 impl Extend<SpinSqliteQR> for SpinSqliteQR {
-    fn extend<T: IntoIterator<Item = SpinSqliteQR>>(&mut self, _iter: T) {
+    fn extend<T: IntoIterator<Item = SpinSqliteQR>>(&mut self, iter: T) {
+        for qr in iter {
+            self.rows_affected += qr.rows_affected;
+            self.last_insert_rowid = qr.last_insert_rowid;
+        }
     }
 }
 
 impl SpinSqliteQR {
-    // fn rows(&self) -> Vec<spin_sdk::sqlite::Row<'_>> {
-    //     match &self.inner {
-    //         Some(qr) => qr.rows().collect(),
-    //         None => vec![],
-    //     }
-    // }
-
-    // Looks like SQLite doesn't give us this back?
-    // pub fn rows_affected(&self) -> u64 {
-    //     match &self.inner {
-    //         None => panic!("what the heck, rows_affected called on empty"),
-    //         Some(qr) => match &qr.rows[0].values[0] {
-    //             &spin_sdk::sqlite::Value::Integer(n) => n.try_into().unwrap(),
-    //             _ => panic!("NAN NAN NAN"),
-    //         }
-    //     }
-    // }
+    pub fn rows_affected(&self) -> u64 {
+        self.rows_affected
+    }
+
+    /// The rowid of the most recent successful INSERT on the connection.
+    /// Like SQLite's `last_insert_rowid()`, this is not reset by statements
+    /// that don't insert.
+    pub fn last_insert_rowid(&self) -> i64 {
+        self.last_insert_rowid
+    }
 }
 
 impl sqlx::Column for SpinSqliteColumn {
     type Database = SqlxConnection;
 
     fn ordinal(&self) -> usize {
-        todo!()
+        self.ordinal
     }
 
     fn name(&self) -> &str {
-        todo!()
+        &self.name
     }
 
     fn type_info(&self) -> &<Self::Database as sqlx::Database>::TypeInfo {
-        todo!()
+        &self.type_info
     }
 }
 
@@ -193,6 +702,29 @@ impl Display for SpinSqliteTypeInfo {
         f.write_str(self.name())
     }
 }
+impl From<&spin_sdk::sqlite::Value> for SpinSqliteTypeInfo {
+    fn from(value: &spin_sdk::sqlite::Value) -> Self {
+        match value {
+            spin_sdk::sqlite::Value::Null => Self::Null,
+            spin_sdk::sqlite::Value::Integer(_) => Self::Int,
+            spin_sdk::sqlite::Value::Blob(_) => Self::Blob,
+            spin_sdk::sqlite::Value::Real(_) => Self::Real,
+            spin_sdk::sqlite::Value::Text(_) => Self::Text,
+        }
+    }
+}
+
+impl SpinSqliteTypeInfo {
+    /// Whether a value of type `other` can be decoded as this type.  NULL
+    /// goes with anything, as SQLite lets any column hold it, and integers
+    /// can be read as reals.
+    pub fn compatible(&self, other: &Self) -> bool {
+        self == other
+            || *other == Self::Null
+            || (*self == Self::Real && *other == Self::Int)
+    }
+}
+
 impl sqlx::TypeInfo for SpinSqliteTypeInfo {
     fn is_null(&self) -> bool {
         *self == Self::Null
@@ -213,15 +745,15 @@ impl sqlx::Value for SpinSqliteValue {
     type Database = SqlxConnection;
 
     fn as_ref(&self) -> <Self::Database as sqlx::database::HasValueRef<'_>>::ValueRef {
-        todo!()
+        SpinSqliteValueRef { inner: &self.inner, lenient: self.lenient }
     }
 
     fn type_info(&self) -> std::borrow::Cow<'_, <Self::Database as sqlx::Database>::TypeInfo> {
-        todo!()
+        std::borrow::Cow::Owned(SpinSqliteTypeInfo::from(&self.inner))
     }
 
     fn is_null(&self) -> bool {
-        todo!()
+        matches!(self.inner, spin_sdk::sqlite::Value::Null)
     }
 }
 
@@ -247,14 +779,18 @@ impl SpinSqliteArgs {
 impl<'q> sqlx::Arguments<'q> for SpinSqliteArgs {
     type Database = SqlxConnection;
 
-    fn reserve(&mut self, additional: usize, size: usize) {
-        todo!()
+    fn reserve(&mut self, additional: usize, _size: usize) {
+        self.inner.reserve(additional)
     }
 
     fn add<T>(&mut self, value: T)
     where
         T: 'q + Send + sqlx::Encode<'q, Self::Database> + sqlx::Type<Self::Database> {
-        let _ = value.encode_by_ref(&mut self.inner);
+        // As with upstream's drivers, Encode says a value is NULL rather than
+        // pushing one
+        if let sqlx::encode::IsNull::Yes = value.encode(&mut self.inner) {
+            self.inner.push(spin_sdk::sqlite::Value::Null);
+        }
     }
 }
 
@@ -270,101 +806,138 @@ impl<'q> sqlx::database::HasStatement<'q> for SqlxConnection {
     type Statement = SpinSqliteStmt;
 }
 
-#[derive(Default)]
+/// A prepared statement.  The host has no prepared statements of its own,
+/// so this is the SQL, run afresh each time, plus what `describe` found out
+/// about it.  `columns()` is empty for statements that only return rows
+/// through `RETURNING`, as finding their names means running them.
+#[derive(Clone, Debug, Default)]
 pub struct SpinSqliteStmt {
-
+    sql: String,
+    parameters: usize,
+    columns: std::sync::Arc<Vec<SpinSqliteColumn>>,
 }
 
 impl<'q> sqlx::Statement<'q> for SpinSqliteStmt {
     type Database = SqlxConnection;
 
     fn to_owned(&self) -> <Self::Database as sqlx::database::HasStatement<'static>>::Statement {
-        todo!()
+        self.clone()
     }
 
     fn sql(&self) -> &str {
-        todo!()
+        &self.sql
     }
 
     fn parameters(&self) -> Option<either::Either<&[<Self::Database as sqlx::Database>::TypeInfo], usize>> {
-        todo!()
+        Some(either::Either::Right(self.parameters))
     }
 
     fn columns(&self) -> &[<Self::Database as sqlx::Database>::Column] {
-        todo!()
+        &self.columns
     }
 
-    fn query(&self) -> sqlx::query::Query<'_, Self::Database, <Self::Database as sqlx::database::HasArguments<'_>>::Arguments> {
-        todo!()
-    }
-
-    fn query_with<'s, A>(&'s self, arguments: A) -> sqlx::query::Query<'s, Self::Database, A>
-    where
-        A: sqlx::IntoArguments<'s, Self::Database> {
-        todo!()
-    }
-
-    fn query_as<O>(
-        &self,
-    ) -> sqlx::query::QueryAs<'_, Self::Database, O, <Self::Database as sqlx::database::HasArguments<'_>>::Arguments>
-    where
-        O: for<'r> sqlx::FromRow<'r, <Self::Database as sqlx::Database>::Row> {
-        todo!()
-    }
+    sqlx_core::impl_statement_query!(SpinSqliteArgs);
+}
 
-    fn query_as_with<'s, O, A>(&'s self, arguments: A) -> sqlx::query::QueryAs<'s, Self::Database, O, A>
-    where
-        O: for<'r> sqlx::FromRow<'r, <Self::Database as sqlx::Database>::Row>,
-        A: sqlx::IntoArguments<'s, Self::Database> {
-        todo!()
+// Counts parameters the way SQLite numbers them: `?` takes the next number,
+// `?NNN` takes NNN, and `:name`, `@name` and `$name` take the next number the
+// first time each name appears.  The count is the highest number used.
+fn count_parameters(sql: &str) -> usize {
+    fn is_ident_byte(b: u8) -> bool {
+        b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80
     }
 
-    fn query_scalar<O>(
-        &self,
-    ) -> sqlx::query::QueryScalar<'_, Self::Database, O, <Self::Database as sqlx::database::HasArguments<'_>>::Arguments>
-    where
-        (O,): for<'r> sqlx::FromRow<'r, <Self::Database as sqlx::Database>::Row> {
-        todo!()
+    let bytes = sql.as_bytes();
+    let mut count = 0;
+    let mut names: Vec<&str> = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            // Skip literals and quoted identifiers.  A doubled quote just
+            // looks like two literals back to back, which is fine.
+            quote @ (b'\'' | b'"' | b'`') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += 1;
+                }
+                i += 1;
+            },
+            b'[' => {
+                while i < bytes.len() && bytes[i] != b']' {
+                    i += 1;
+                }
+                i += 1;
+            },
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            },
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    i += 1;
+                }
+                i += 2;
+            },
+            b'?' => {
+                i += 1;
+                let start = i;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                count = match sql[start..i].parse::<usize>() {
+                    Ok(n) => count.max(n),
+                    Err(_) => count + 1,
+                };
+            },
+            b':' | b'@' | b'$' if i == 0 || !is_ident_byte(bytes[i - 1]) => {
+                let start = i + 1;
+                i = start;
+                while i < bytes.len() && is_ident_byte(bytes[i]) {
+                    i += 1;
+                }
+                let name = &sql[start..i];
+                if !name.is_empty() && !names.contains(&name) {
+                    names.push(name);
+                    count += 1;
+                }
+            },
+            _ => i += 1,
+        }
     }
 
-    fn query_scalar_with<'s, O, A>(&'s self, arguments: A) -> sqlx::query::QueryScalar<'s, Self::Database, O, A>
-    where
-        (O,): for<'r> sqlx::FromRow<'r, <Self::Database as sqlx::Database>::Row>,
-        A: sqlx::IntoArguments<'s, Self::Database> {
-        todo!()
-    }
+    count
 }
 
 impl<'q> sqlx::database::HasValueRef<'q> for SqlxConnection {
     type Database = SqlxConnection;
 
-    type ValueRef = SpinSqliteValueRef;
+    type ValueRef = SpinSqliteValueRef<'q>;
 }
 
-pub struct SpinSqliteValueRef {
-    inner: spin_sdk::sqlite::Value,
+// Borrows from the row, so text and blobs can be decoded without copying
+pub struct SpinSqliteValueRef<'r> {
+    inner: &'r spin_sdk::sqlite::Value,
+    // Whether to decode by SQLite's affinity rules; see
+    // SqlxConnection::set_lenient_decoding
+    lenient: bool,
 }
 
-impl<'q> sqlx::ValueRef<'q> for SpinSqliteValueRef {
+impl<'r> sqlx::ValueRef<'r> for SpinSqliteValueRef<'r> {
     type Database = SqlxConnection;
 
     fn to_owned(&self) -> <Self::Database as sqlx::Database>::Value {
-        todo!()
+        SpinSqliteValue { inner: self.inner.clone(), lenient: self.lenient }
     }
 
     fn type_info(&self) -> std::borrow::Cow<'_, <Self::Database as sqlx::Database>::TypeInfo> {
-        let type_info = match &self.inner {
-            spin_sdk::sqlite::Value::Null => SpinSqliteTypeInfo::Null,
-            spin_sdk::sqlite::Value::Integer(_) => SpinSqliteTypeInfo::Int,
-            spin_sdk::sqlite::Value::Blob(_) => SpinSqliteTypeInfo::Blob,
-            spin_sdk::sqlite::Value::Real(_) => SpinSqliteTypeInfo::Real,
-            spin_sdk::sqlite::Value::Text(_) => SpinSqliteTypeInfo::Text,
-        };
-        std::borrow::Cow::Owned(type_info)
+        std::borrow::Cow::Owned(SpinSqliteTypeInfo::from(self.inner))
     }
 
     fn is_null(&self) -> bool {
-        matches!(&self.inner, spin_sdk::sqlite::Value::Null)
+        matches!(self.inner, spin_sdk::sqlite::Value::Null)
     }
 }
 
@@ -374,59 +947,206 @@ impl sqlx::TransactionManager for SqlxConnection {
     fn begin(
         conn: &mut <Self::Database as sqlx::Database>::Connection,
     ) -> BoxFuture<'_, Result<(), sqlx::Error>> {
-        todo!()
+        Box::pin(async move {
+            let mode = conn.next_transaction_mode.take().unwrap_or(conn.transaction_mode);
+            let sql = begin_transaction_sql(conn.transaction_depth, mode);
+            conn.execute_sql(&sql)?;
+            conn.transaction_depth += 1;
+            Ok(())
+        })
     }
 
     fn commit(
         conn: &mut <Self::Database as sqlx::Database>::Connection,
     ) -> BoxFuture<'_, Result<(), sqlx::Error>> {
-        todo!()
+        Box::pin(async move {
+            if conn.transaction_depth > 0 {
+                let sql = commit_transaction_sql(conn.transaction_depth);
+                conn.execute_sql(&sql)?;
+                conn.transaction_depth -= 1;
+            }
+            Ok(())
+        })
     }
 
     fn rollback(
         conn: &mut <Self::Database as sqlx::Database>::Connection,
     ) -> BoxFuture<'_, Result<(), sqlx::Error>> {
-        todo!()
+        Box::pin(async move {
+            if conn.transaction_depth > 0 {
+                let sql = rollback_transaction_sql(conn.transaction_depth);
+                conn.execute_sql(&sql)?;
+                conn.transaction_depth -= 1;
+            }
+            Ok(())
+        })
     }
 
     fn start_rollback(conn: &mut <Self::Database as sqlx::Database>::Connection) {
-        todo!()
+        // Host calls are synchronous so we can roll back right here rather
+        // than queueing it for the next use of the connection.  There's no one
+        // to report an error to (this is called from Drop) so swallow it.
+        if conn.transaction_depth > 0 {
+            let sql = rollback_transaction_sql(conn.transaction_depth);
+            let _ = conn.execute_sql(&sql);
+            conn.transaction_depth -= 1;
+        }
+    }
+}
+
+// SQLite won't BEGIN inside a transaction, so anything past the outermost
+// transaction becomes a savepoint.  `depth` is the depth *before* the begin,
+// or *before* the commit/rollback (so 1 means the outermost transaction).
+fn begin_transaction_sql(depth: usize, mode: TransactionMode) -> std::borrow::Cow<'static, str> {
+    if depth == 0 {
+        std::borrow::Cow::Borrowed(mode.begin_sql())
+    } else {
+        std::borrow::Cow::Owned(format!("SAVEPOINT sqlx_savepoint_{depth}"))
+    }
+}
+
+fn commit_transaction_sql(depth: usize) -> std::borrow::Cow<'static, str> {
+    if depth == 1 {
+        std::borrow::Cow::Borrowed("COMMIT")
+    } else {
+        std::borrow::Cow::Owned(format!("RELEASE sqlx_savepoint_{}", depth - 1))
+    }
+}
+
+fn rollback_transaction_sql(depth: usize) -> std::borrow::Cow<'static, str> {
+    if depth == 1 {
+        std::borrow::Cow::Borrowed("ROLLBACK")
+    } else {
+        // This leaves the savepoint on SQLite's stack, but a later SAVEPOINT
+        // of the same name shadows it and the outer COMMIT/ROLLBACK clears it
+        std::borrow::Cow::Owned(format!("ROLLBACK TO sqlx_savepoint_{}", depth - 1))
     }
 }
 
 impl std::str::FromStr for SqlxConnectionOptions {
     type Err = sqlx::Error;
 
+    // Either a URL or just a label
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self { label: s.to_owned() })
+        if s.contains(':') {
+            let url = s.parse::<url::Url>()
+                .map_err(|e| config_error(format!("invalid database URL {s:?}: {e}")))?;
+            sqlx::ConnectOptions::from_url(&url)
+        } else if s.is_empty() {
+            Err(config_error("database label must not be empty".to_owned()))
+        } else {
+            Ok(Self::with_label(s.to_owned()))
+        }
+    }
+}
+
+fn config_error(message: String) -> sqlx::Error {
+    sqlx::Error::Configuration(message.into())
+}
+
+fn parse_url_param<T>(key: &str, value: &str, parse: impl FnOnce(&str) -> Option<T>) -> Result<T, sqlx::Error> {
+    parse(value).ok_or_else(|| config_error(format!("invalid value {value:?} for database URL parameter {key:?}")))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
     }
 }
 
+// Everything but unreserved characters, so a label can't be mistaken for
+// another part of the URL
+const LABEL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+fn decode_label(encoded: &str) -> Result<String, sqlx::Error> {
+    percent_decode_str(encoded)
+        .decode_utf8()
+        .map(|label| label.into_owned())
+        .map_err(|_| config_error(format!("database label {encoded:?} is not UTF-8")))
+}
+
 use log::LevelFilter;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 
 impl sqlx::ConnectOptions for SqlxConnectionOptions {
     type Connection = SqlxConnection;
 
+    // Accepts spin-sqlite://<label> or spin-sqlite:<label>, with optional
+    // settings as query parameters
     fn from_url(url: &url::Url) -> Result<Self, sqlx::Error> {
-        let label = url.host().unwrap().to_string();  // TODO: figure out the stupid error type
-        Ok(Self { label })
+        if url.scheme() != "spin-sqlite" {
+            return Err(config_error(format!("unsupported database URL scheme {:?}, expected \"spin-sqlite\"", url.scheme())));
+        }
+
+        let label = match url.host_str() {
+            Some(host) if !host.is_empty() => {
+                if !matches!(url.path(), "" | "/") {
+                    return Err(config_error(format!("unexpected path {:?} in database URL", url.path())));
+                }
+                decode_label(host)?
+            },
+            _ => decode_label(url.path().trim_start_matches('/'))?,
+        };
+        if label.is_empty() {
+            return Err(config_error(format!("database URL {:?} does not name a database, e.g. spin-sqlite://default", url.as_str())));
+        }
+
+        let mut options = Self::with_label(label);
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "mode" => options.read_only = parse_url_param(&key, &value, |v| match v {
+                    "ro" => Some(true),
+                    "rw" => Some(false),
+                    _ => None,
+                })?,
+                "foreign_keys" => options.foreign_keys = Some(parse_url_param(&key, &value, parse_bool)?),
+                "busy_retries" => options.busy_retries = parse_url_param(&key, &value, |v| v.parse().ok())?,
+                "transaction_mode" => options.transaction_mode = parse_url_param(&key, &value, TransactionMode::from_url_param)?,
+                "lenient_decoding" => options.lenient_decoding = parse_url_param(&key, &value, parse_bool)?,
+                "case_insensitive_column_names" => options.case_insensitive_column_names = parse_url_param(&key, &value, parse_bool)?,
+                _ => return Err(config_error(format!("unknown database URL parameter {key:?}"))),
+            }
+        }
+
+        Ok(options)
     }
 
     fn connect(&self) -> BoxFuture<'_, Result<Self::Connection, sqlx::Error>>
     where
         Self::Connection: Sized {
             Box::pin(async move {
-                spin_sdk::sqlite::Connection::open(&self.label)
-                    .map(|conn| SqlxConnection(conn))
-                    .map_err(|e| sqlx::Error::AnyDriverError(Box::new(e)))
+                let conn = HostConnection::open(&self.label)
+                    .map_err(|e| sqlx::Error::from(SpinSqliteDatabaseError::from(e)))?;
+                let mut conn = SqlxConnection::new(conn);
+                conn.set_transaction_mode(self.transaction_mode);
+                conn.set_busy_retries(self.busy_retries);
+                conn.set_lenient_decoding(self.lenient_decoding);
+                conn.set_case_insensitive_column_names(self.case_insensitive_column_names);
+                conn.log_settings = self.log_settings.clone();
+                // The host doesn't let us choose how the file is opened, so
+                // read-only is the best SQLite can do from inside
+                if self.read_only {
+                    conn.execute_sql("PRAGMA query_only = ON")?;
+                }
+                match self.foreign_keys {
+                    Some(true) => { conn.execute_sql("PRAGMA foreign_keys = ON")?; },
+                    Some(false) => { conn.execute_sql("PRAGMA foreign_keys = OFF")?; },
+                    None => (),
+                }
+                Ok(conn)
             })
     }
 
-    fn log_statements(self, _level: LevelFilter) -> Self {
+    fn log_statements(mut self, level: LevelFilter) -> Self {
+        self.log_settings.statements_level = level;
         self
     }
 
-    fn log_slow_statements(self, _level: LevelFilter, _duration: std::time::Duration) -> Self {
+    fn log_slow_statements(mut self, level: LevelFilter, duration: std::time::Duration) -> Self {
+        self.log_settings.slow_statements_level = level;
+        self.log_settings.slow_statements_duration = duration;
         self
     }
 }
@@ -456,23 +1176,23 @@ impl<'c> sqlx::Executor<'c> for &'c SqlxConnection {
     where
         'c: 'e,
         E: sqlx::Execute<'q, Self::Database> {
-
-        println!("FETCH-MANYing {}", query.sql());
-        // The args-exec dance needs to go on the SqlxConnection object
-        let args = query.take_arguments().unwrap_or_default();
-        let rs = self.0.execute(query.sql(), args.as_slice()).unwrap();
-
-        // Okay this CANNOT return a QueryResult because fetch will filtermap any
-        // Either::Lefts away because reasons.  We have to get the rows.
-
-        // let qr = SpinSqliteQR { inner: Some(rs) };
-        // let res = Ok(sqlx::Either::Left(qr));
-        // Box::pin(futures::stream::once(async { res }))
-
-        let columns = std::sync::Arc::new(rs.columns.clone());
-        let rows = rs.rows.into_iter()
-            .map(move |r| Ok(sqlx::Either::Right(SpinSqliteRow { columns: columns.clone(), inner: r })));
-        Box::pin(futures::stream::iter(rows))
+        use futures::stream::StreamExt;
+
+        // Nothing happens until the stream is polled, same as other drivers
+        let results = futures::stream::once(async move {
+            let args = query.take_arguments().unwrap_or_default();
+            let results: Vec<_> = match self.execute_counted(query.sql(), args.as_slice()) {
+                // The rows, then the QR for the statement as a Left.  fetch
+                // filters out the Left and execute_many filters out the rows.
+                Ok((rs, qr)) => self.rows_from(rs)
+                    .map(|r| Ok(sqlx::Either::Right(r)))
+                    .chain(std::iter::once(Ok(sqlx::Either::Left(qr))))
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(results)
+        });
+        Box::pin(results.flatten())
     }
 
     fn execute<'e, 'q: 'e, E: 'q>(
@@ -482,13 +1202,11 @@ impl<'c> sqlx::Executor<'c> for &'c SqlxConnection {
         where
             'c: 'e,
             E: sqlx::Execute<'q, Self::Database>, {
-        println!("EXECing {}", query.sql());
-        let args = query.take_arguments().unwrap_or_default();
-        let rs = self.0.execute(query.sql(), args.as_slice()).unwrap();
-
-        let qr = SpinSqliteQR { /*inner: Some(rs)*/ };
-        let res = Ok(qr);
-        Box::pin(async { res })
+        Box::pin(async move {
+            let args = query.take_arguments().unwrap_or_default();
+            self.execute_counted(query.sql(), args.as_slice())
+                .map(|(_rs, qr)| qr)
+        })
     }
 
     fn fetch_optional<'e, 'q: 'e, E: 'q>(
@@ -498,16 +1216,86 @@ impl<'c> sqlx::Executor<'c> for &'c SqlxConnection {
     where
         'c: 'e,
         E: sqlx::Execute<'q, Self::Database> {
-        println!("FETCH-OPTIONALing {}", query.sql());
-        let args = query.take_arguments().unwrap_or_default();
-        let rs = self.0.execute(query.sql(), args.as_slice()).unwrap();
+        Box::pin(async move {
+            let args = query.take_arguments().unwrap_or_default();
+            self.execute_logged(query.sql(), args.as_slice())
+                .map(|rs| self.rows_from(rs).next())
+        })
+    }
 
-        let columns = std::sync::Arc::new(rs.columns.clone());
-        let row = rs.rows.into_iter()
-            .map(move |r| SpinSqliteRow { columns: columns.clone(), inner: r })
-            .next();
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        _parameters: &'e [<Self::Database as sqlx::Database>::TypeInfo],
+    ) -> BoxFuture<'e, Result<<Self::Database as sqlx::database::HasStatement<'q>>::Statement, sqlx::Error>>
+    where
+        'c: 'e {
+        Box::pin(async move {
+            // Not allowed to run the statement: preparing shouldn't change
+            // anything
+            let describe = self.describe_statement(sql, false)?;
+            Ok(SpinSqliteStmt {
+                sql: sql.to_owned(),
+                parameters: count_parameters(sql),
+                columns: std::sync::Arc::new(describe.columns),
+            })
+        })
+    }
 
-        Box::pin(async { Ok(row) })
+    fn describe<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+    ) -> BoxFuture<'e, Result<sqlx::Describe<Self::Database>, sqlx::Error>>
+    where
+        'c: 'e {
+        Box::pin(async move { self.describe_statement(sql, true) })
+    }
+}
+
+// For code written against `&mut` connections, and for transactions, which
+// deref to the connection: `query.execute(&mut *tx)`.  (We can't implement
+// Executor for `&mut Transaction` itself as neither the trait nor the type
+// is ours.)
+impl<'c> sqlx::Executor<'c> for &'c mut SqlxConnection {
+    type Database = SqlxConnection;
+
+    fn fetch_many<'e, 'q: 'e, E: 'q>(
+        self,
+        query: E,
+    ) -> BoxStream<
+        'e,
+        Result<
+            sqlx::Either<<Self::Database as sqlx::Database>::QueryResult, <Self::Database as sqlx::Database>::Row>,
+            sqlx::Error,
+        >,
+    >
+    where
+        'c: 'e,
+        E: sqlx::Execute<'q, Self::Database> {
+        let conn: &'c SqlxConnection = self;
+        sqlx::Executor::fetch_many(conn, query)
+    }
+
+    fn execute<'e, 'q: 'e, E: 'q>(
+            self,
+            query: E,
+        ) -> BoxFuture<'e, Result<<Self::Database as sqlx::Database>::QueryResult, sqlx::Error>>
+        where
+            'c: 'e,
+            E: sqlx::Execute<'q, Self::Database>, {
+        let conn: &'c SqlxConnection = self;
+        sqlx::Executor::execute(conn, query)
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E: 'q>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<<Self::Database as sqlx::Database>::Row>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: sqlx::Execute<'q, Self::Database> {
+        let conn: &'c SqlxConnection = self;
+        sqlx::Executor::fetch_optional(conn, query)
     }
 
     fn prepare_with<'e, 'q: 'e>(
@@ -517,7 +1305,8 @@ impl<'c> sqlx::Executor<'c> for &'c SqlxConnection {
     ) -> BoxFuture<'e, Result<<Self::Database as sqlx::database::HasStatement<'q>>::Statement, sqlx::Error>>
     where
         'c: 'e {
-        todo!()
+        let conn: &'c SqlxConnection = self;
+        sqlx::Executor::prepare_with(conn, sql, parameters)
     }
 
     fn describe<'e, 'q: 'e>(
@@ -526,11 +1315,10 @@ impl<'c> sqlx::Executor<'c> for &'c SqlxConnection {
     ) -> BoxFuture<'e, Result<sqlx::Describe<Self::Database>, sqlx::Error>>
     where
         'c: 'e {
-            todo!()
-        }
-
-
-    // fn describe<'q>(self, _: &'q str) -> std::pin::Pin<Box<
-    //     (dyn futures_core::Future<Output = Result<sqlx::Describe<<Self as sqlx::Executor<'c>>::Database>, sqlx::Error>> + std::marker::Send + 'e)>> { todo!() }`: `fn describe(self, _: &'q str) -> Pin<Box<(dyn futures_core::Future<Output = Result<Describe<<Self as Executor<'c>>::Database>, sqlx::Error>> + std::marker::Send + 'e)>> { todo!() }
+        let conn: &'c SqlxConnection = self;
+        sqlx::Executor::describe(conn, sql)
+    }
 }
 
+// Acquire for `&mut Transaction<SqlxConnection>` comes free with sqlx
+sqlx_core::impl_acquire!(SqlxConnection, SqlxConnection);
@@ -1,33 +1,31 @@
-use std::fmt::Display;
+use std::borrow::Cow;
+use std::sync::Arc;
 
-use super::{SqlxConnection, SpinSqliteTypeInfo};
+use super::{SqlxConnection, SpinSqliteDecodeError, SpinSqliteTypeInfo, SpinSqliteValueRef};
 
-// anyhow::Error makes sqlx mad
-#[derive(Debug)]
-struct BadTypeError;
-impl std::error::Error for BadTypeError {}
-impl Display for BadTypeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("bad type")
+// NULL gets its own error, as it's usually a column that wants to be decoded
+// as an Option
+fn wrong_type<T>(value: &spin_sdk::sqlite::Value) -> sqlx::error::BoxDynError {
+    match value {
+        spin_sdk::sqlite::Value::Null => Box::new(sqlx::error::UnexpectedNullError),
+        _ => Box::new(SpinSqliteDecodeError::wrong_type(std::any::type_name::<T>(), value)),
     }
 }
 
-#[derive(Debug)]
-struct BadValError;
-impl std::error::Error for BadValError {}
-impl Display for BadValError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("bad value")
-    }
+// For lenient decoding: text that SQLite would have converted had it been
+// stored in a column of the right affinity, e.g. '42' in an INTEGER column.
+// Errors are reported against T, the type being decoded.
+fn parse_text<P: std::str::FromStr, T>(s: &str) -> Result<P, sqlx::error::BoxDynError> {
+    s.trim().parse().map_err(|_| wrong_type::<T>(&spin_sdk::sqlite::Value::Text(s.to_owned())))
 }
 
-
-
-
-fn into_or_err<T: TryInto<U>, U>(value: T) -> Result<U, sqlx::error::BoxDynError> {
-    match value.try_into() {
+fn into_or_err<U: TryFrom<i64>>(value: i64) -> Result<U, sqlx::error::BoxDynError> {
+    match U::try_from(value) {
         Ok(v) => Ok(v),
-        Err(e) => Err(Box::new(BadValError)),
+        Err(_) => Err(Box::new(SpinSqliteDecodeError::out_of_range(
+            std::any::type_name::<U>(),
+            &spin_sdk::sqlite::Value::Integer(value),
+        ))),
     }
 }
 
@@ -46,8 +44,8 @@ impl sqlx::Type<SqlxConnection> for &str {
 impl<'r> sqlx::Decode<'r, SqlxConnection> for String {
     fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
         match value.inner {
-            spin_sdk::sqlite::Value::Text(s) => Ok(s),
-            _ => Err(Box::new(BadTypeError)),
+            spin_sdk::sqlite::Value::Text(s) => Ok(s.clone()),
+            other => Err(wrong_type::<Self>(other)),
         }
     }
 }
@@ -56,24 +54,138 @@ impl sqlx::Type<SqlxConnection> for String {
         SpinSqliteTypeInfo::Text
     }
 }
+impl<'q> sqlx::Encode<'q, SqlxConnection> for String {
+    fn encode(self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+        buf.push(spin_sdk::sqlite::Value::Text(self));
+        sqlx::encode::IsNull::No
+    }
 
-impl<'q> sqlx::Encode<'q, SqlxConnection> for u32 {
     fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
-        buf.push(spin_sdk::sqlite::Value::Integer((*self).into()));
+        buf.push(spin_sdk::sqlite::Value::Text(self.clone()));
         sqlx::encode::IsNull::No
     }
 }
-impl<'r> sqlx::Decode<'r, SqlxConnection> for u32 {
+
+impl<'r> sqlx::Decode<'r, SqlxConnection> for &'r str {
     fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
         match value.inner {
-            spin_sdk::sqlite::Value::Integer(n) => into_or_err(n),
-            _ => Err(Box::new(BadTypeError)),
+            spin_sdk::sqlite::Value::Text(s) => Ok(s),
+            other => Err(wrong_type::<Self>(other)),
         }
     }
 }
-impl sqlx::Type<SqlxConnection> for u32 {
-    fn type_info() -> <SqlxConnection as sqlx::Database>::TypeInfo {
-        SpinSqliteTypeInfo::Int
+
+// Other ways of holding a string or bytes, which the host only deals in as
+// String and Vec<u8>.  Decoding copies straight from the row.
+macro_rules! impl_shared {
+    ($variant:ident, $unsized:ty, $convert:ident, $($t:ty),*) => {
+        $(
+            impl<'q> sqlx::Encode<'q, SqlxConnection> for $t {
+                fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+                    buf.push(spin_sdk::sqlite::Value::$variant(self.$convert()));
+                    sqlx::encode::IsNull::No
+                }
+            }
+            impl<'r> sqlx::Decode<'r, SqlxConnection> for $t {
+                fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
+                    <&'r $unsized as sqlx::Decode<'r, SqlxConnection>>::decode(value).map(Into::into)
+                }
+            }
+            impl sqlx::Type<SqlxConnection> for $t {
+                fn type_info() -> <SqlxConnection as sqlx::Database>::TypeInfo {
+                    SpinSqliteTypeInfo::$variant
+                }
+            }
+        )*
+    };
+}
+
+impl_shared!(Text, str, to_string, Box<str>, Arc<str>);
+impl_shared!(Blob, [u8], to_vec, Box<[u8]>, Arc<[u8]>);
+
+// Cow decodes borrowing from the row, rather than going through an owned copy
+macro_rules! impl_cow {
+    ($variant:ident, $unsized:ty, $convert:ident) => {
+        impl<'q> sqlx::Encode<'q, SqlxConnection> for Cow<'_, $unsized> {
+            fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+                buf.push(spin_sdk::sqlite::Value::$variant(self.$convert()));
+                sqlx::encode::IsNull::No
+            }
+        }
+        impl<'r> sqlx::Decode<'r, SqlxConnection> for Cow<'r, $unsized> {
+            fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
+                <&'r $unsized as sqlx::Decode<'r, SqlxConnection>>::decode(value).map(Cow::Borrowed)
+            }
+        }
+        impl sqlx::Type<SqlxConnection> for Cow<'_, $unsized> {
+            fn type_info() -> <SqlxConnection as sqlx::Database>::TypeInfo {
+                SpinSqliteTypeInfo::$variant
+            }
+        }
+    };
+}
+
+impl_cow!(Text, str, to_string);
+impl_cow!(Blob, [u8], to_vec);
+
+// SQLite integers are i64, so these all fit going in, and are range checked
+// coming out
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl<'q> sqlx::Encode<'q, SqlxConnection> for $t {
+                fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+                    buf.push(spin_sdk::sqlite::Value::Integer((*self).into()));
+                    sqlx::encode::IsNull::No
+                }
+            }
+            impl_integer_decode!($t);
+        )*
+    };
+}
+
+macro_rules! impl_integer_decode {
+    ($t:ty) => {
+        impl<'r> sqlx::Decode<'r, SqlxConnection> for $t {
+            fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
+                match value.inner {
+                    spin_sdk::sqlite::Value::Integer(n) => into_or_err(*n),
+                    spin_sdk::sqlite::Value::Text(s) if value.lenient => into_or_err(parse_text::<i64, Self>(s)?),
+                    other => Err(wrong_type::<Self>(other)),
+                }
+            }
+        }
+        impl sqlx::Type<SqlxConnection> for $t {
+            fn type_info() -> <SqlxConnection as sqlx::Database>::TypeInfo {
+                SpinSqliteTypeInfo::Int
+            }
+        }
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, u8, u16, u32);
+
+impl<'q> sqlx::Encode<'q, SqlxConnection> for isize {
+    fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+        // isize is no wider than 64 bits on anything Rust targets
+        buf.push(spin_sdk::sqlite::Value::Integer(*self as i64));
+        sqlx::encode::IsNull::No
+    }
+}
+impl_integer_decode!(isize);
+
+// Decode only, as with sqlx-sqlite: a u64 above i64::MAX has no INTEGER to
+// go in as, and Encode can't fail.  Bind those as i64 (or a string) instead.
+impl_integer_decode!(u64);
+impl_integer_decode!(usize);
+
+// Narrower usizes always fit, as on wasm32 where Spin components run, so
+// `.bind(v.len())` works there
+#[cfg(not(target_pointer_width = "64"))]
+impl<'q> sqlx::Encode<'q, SqlxConnection> for usize {
+    fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+        buf.push(spin_sdk::sqlite::Value::Integer(*self as i64));
+        sqlx::encode::IsNull::No
     }
 }
 
@@ -88,8 +200,13 @@ impl<'r> sqlx::Decode<'r, SqlxConnection> for bool {
         match value.inner {
             spin_sdk::sqlite::Value::Integer(0) => Ok(false),
             spin_sdk::sqlite::Value::Integer(1) => Ok(true),
-            spin_sdk::sqlite::Value::Integer(_) => Err(Box::new(BadValError)),
-            _ => Err(Box::new(BadTypeError)),
+            n @ spin_sdk::sqlite::Value::Integer(_) => Err(Box::new(SpinSqliteDecodeError::out_of_range("bool", n))),
+            spin_sdk::sqlite::Value::Text(s) if value.lenient => match s.trim().to_ascii_lowercase().as_str() {
+                "true" | "t" | "1" => Ok(true),
+                "false" | "f" | "0" => Ok(false),
+                _ => Err(wrong_type::<Self>(value.inner)),
+            },
+            other => Err(wrong_type::<Self>(other)),
         }
     }
 }
@@ -99,17 +216,73 @@ impl sqlx::Type<SqlxConnection> for bool {
     }
 }
 
+// SQLite stores a NaN as NULL, so we bind it as one rather than leave that to
+// the host.  Infinities are stored as they are.
+fn real_value(n: f64, buf: &mut Vec<spin_sdk::sqlite::Value>) -> sqlx::encode::IsNull {
+    if n.is_nan() {
+        sqlx::encode::IsNull::Yes
+    } else {
+        buf.push(spin_sdk::sqlite::Value::Real(n));
+        sqlx::encode::IsNull::No
+    }
+}
+
+// Numeric expressions (e.g. SUM over an integer column) hand back integers
+// even where a float is wanted, so we take those too as long as nothing is
+// lost.
+fn decode_real<T>(value: SpinSqliteValueRef<'_>) -> Result<f64, sqlx::error::BoxDynError> {
+    match *value.inner {
+        spin_sdk::sqlite::Value::Real(n) => Ok(n),
+        spin_sdk::sqlite::Value::Integer(n) => {
+            let real = n as f64;
+            // Compared as i128 because i64::MAX rounds up to 2^63, which
+            // would saturate back to i64::MAX
+            if real as i128 == i128::from(n) {
+                Ok(real)
+            } else {
+                Err(Box::new(SpinSqliteDecodeError::inexact(std::any::type_name::<T>(), &spin_sdk::sqlite::Value::Integer(n))))
+            }
+        },
+        spin_sdk::sqlite::Value::Text(ref s) if value.lenient => parse_text::<f64, T>(s),
+        ref other => Err(wrong_type::<T>(other)),
+    }
+}
+
+impl<'q> sqlx::Encode<'q, SqlxConnection> for f64 {
+    fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+        real_value(*self, buf)
+    }
+}
+impl<'r> sqlx::Decode<'r, SqlxConnection> for f64 {
+    fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
+        decode_real::<Self>(value)
+    }
+}
+impl sqlx::Type<SqlxConnection> for f64 {
+    fn type_info() -> <SqlxConnection as sqlx::Database>::TypeInfo {
+        SpinSqliteTypeInfo::Real
+    }
+
+    fn compatible(ty: &<SqlxConnection as sqlx::Database>::TypeInfo) -> bool {
+        SpinSqliteTypeInfo::Real.compatible(ty)
+    }
+}
+
 impl<'q> sqlx::Encode<'q, SqlxConnection> for f32 {
     fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
-        buf.push(spin_sdk::sqlite::Value::Real((*self).into()));
-        sqlx::encode::IsNull::No
+        real_value((*self).into(), buf)
     }
 }
 impl<'r> sqlx::Decode<'r, SqlxConnection> for f32 {
     fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
-        match value.inner {
-            spin_sdk::sqlite::Value::Real(n) => Ok(n as f32),  // TODO: what could go wrong eh
-            _ => Err(Box::new(BadTypeError)),
+        let n = decode_real::<Self>(value)?;
+        // Rounds to the nearest f32, as reading a REAL into a float column
+        // should; only finite values no f32 can hold are errors.
+        // Infinities are stored as they are, so they come back as they are.
+        if n.is_infinite() || n.abs() <= f64::from(f32::MAX) {
+            Ok(n as f32)
+        } else {
+            Err(Box::new(SpinSqliteDecodeError::out_of_range("f32", &spin_sdk::sqlite::Value::Real(n))))
         }
     }
 }
@@ -117,6 +290,10 @@ impl sqlx::Type<SqlxConnection> for f32 {
     fn type_info() -> <SqlxConnection as sqlx::Database>::TypeInfo {
         SpinSqliteTypeInfo::Real
     }
+
+    fn compatible(ty: &<SqlxConnection as sqlx::Database>::TypeInfo) -> bool {
+        SpinSqliteTypeInfo::Real.compatible(ty)
+    }
 }
 
 impl<'q> sqlx::Encode<'q, SqlxConnection> for &[u8] {
@@ -141,11 +318,30 @@ impl<const N: usize> sqlx::Type<SqlxConnection> for &[u8; N] {
         SpinSqliteTypeInfo::Blob
     }
 }
-impl<'r> sqlx::Decode<'r, SqlxConnection> for Vec<u8> {
+impl<'q> sqlx::Encode<'q, SqlxConnection> for Vec<u8> {
+    fn encode(self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+        buf.push(spin_sdk::sqlite::Value::Blob(self));
+        sqlx::encode::IsNull::No
+    }
+
+    fn encode_by_ref(&self, buf: &mut <SqlxConnection as sqlx::database::HasArguments<'q>>::ArgumentBuffer) -> sqlx::encode::IsNull {
+        buf.push(spin_sdk::sqlite::Value::Blob(self.clone()));
+        sqlx::encode::IsNull::No
+    }
+}
+impl<'r> sqlx::Decode<'r, SqlxConnection> for &'r [u8] {
     fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
         match value.inner {
             spin_sdk::sqlite::Value::Blob(v) => Ok(v),
-            _ => Err(Box::new(BadTypeError)),
+            other => Err(wrong_type::<Self>(other)),
+        }
+    }
+}
+impl<'r> sqlx::Decode<'r, SqlxConnection> for Vec<u8> {
+    fn decode(value: <SqlxConnection as sqlx::database::HasValueRef<'r>>::ValueRef) -> Result<Self, sqlx::error::BoxDynError> {
+        match value.inner {
+            spin_sdk::sqlite::Value::Blob(v) => Ok(v.clone()),
+            other => Err(wrong_type::<Self>(other)),
         }
     }
 }
@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+
+use spin_sdk::sqlite::Value;
+
+use super::{count_parameters, SqlxConnection, SpinSqliteColumn, SpinSqliteTypeInfo};
+
+// The host doesn't expose sqlite3_column_decltype or friends, so to find out
+// what a statement returns we read its bytecode (EXPLAIN) and follow each
+// result column back to the table column it was loaded from, then ask the
+// schema about that column.  Anything we can't trace (expressions, function
+// results) comes back as Null type with unknown nullability.
+//
+// Opcodes are documented at https://www.sqlite.org/opcode.html.
+
+struct Op {
+    opcode: String,
+    p1: i64,
+    p2: i64,
+    p3: i64,
+    p4: Option<String>,
+}
+
+// Where the value in a register came from
+#[derive(Clone, Debug, PartialEq)]
+enum Origin {
+    // A record built by MakeRecord, as fed to and read back from a sorter
+    Record(Vec<Option<Origin>>),
+    Column { cursor: i64, column: i64 },
+    Rowid { cursor: i64 },
+    Constant { type_info: SpinSqliteTypeInfo, nullable: bool },
+    // CAST, which keeps the nullability of what it was given
+    Cast { type_info: SpinSqliteTypeInfo, from: Option<Box<Origin>> },
+}
+
+#[derive(Default)]
+struct Cursor {
+    root_page: i64,
+    // Set if the cursor can produce an all-NULL row, as on the right of a
+    // LEFT JOIN
+    null_row: bool,
+}
+
+struct SchemaColumn {
+    type_info: SpinSqliteTypeInfo,
+    not_null: bool,
+}
+
+impl SqlxConnection {
+    // With `may_run` false, a statement whose column names can only be found
+    // by running it (see column_names) is described as returning no columns
+    pub(super) fn describe_statement(&self, sql: &str, may_run: bool) -> Result<sqlx::Describe<SqlxConnection>, sqlx::Error> {
+        let sql = sql.trim().trim_end_matches(';');
+        let parameters = count_parameters(sql);
+        // The host insists on a value for every parameter
+        let nulls = vec![Value::Null; parameters];
+
+        let program = self.execute_query(&format!("EXPLAIN {sql}"), &nulls)?;
+        let program: Vec<Op> = program.rows.iter().filter_map(|r| Op::from_values(&r.values)).collect();
+
+        let names = if program.iter().any(|op| op.opcode == "ResultRow") {
+            self.column_names(sql, &nulls, may_run)?
+        } else {
+            vec![]
+        };
+
+        let origins = trace_result_columns(&program, names.len());
+        let cursors = cursors(&program);
+
+        let mut columns = Vec::with_capacity(names.len());
+        let mut nullable = Vec::with_capacity(names.len());
+        for (ordinal, (name, origin)) in names.into_iter().zip(origins).enumerate() {
+            let (type_info, is_nullable) = match origin {
+                Some(origin) => self.resolve_origin(&origin, &cursors)?,
+                None => (SpinSqliteTypeInfo::Null, None),
+            };
+            columns.push(SpinSqliteColumn { ordinal, name, type_info });
+            nullable.push(is_nullable);
+        }
+
+        Ok(sqlx::Describe {
+            columns,
+            parameters: Some(either::Either::Right(parameters)),
+            nullable,
+        })
+    }
+
+    // The host only hands back column names alongside a result set.  For a
+    // query, we get them by wrapping it to return no rows, which doesn't run
+    // it.  Anything else that returns rows (INSERT ... RETURNING, or a WITH
+    // leading into one) can only be asked by running it, so with `may_run`
+    // this executes the statement, inside a savepoint that's always rolled
+    // back so it changes nothing.  A statement that fails with NULL
+    // parameters (e.g. an INSERT into a NOT NULL column) can't be described
+    // that way.
+    fn column_names(&self, sql: &str, nulls: &[Value], may_run: bool) -> Result<Vec<String>, sqlx::Error> {
+        let first_word = sql.split_whitespace().next().unwrap_or_default().to_ascii_uppercase();
+        let is_query = matches!(first_word.as_str(), "SELECT" | "VALUES");
+        if is_query || first_word == "WITH" {
+            // On lines of its own so a trailing -- comment can't swallow it
+            match self.execute_query(&format!("SELECT * FROM (\n{sql}\n) LIMIT 0"), nulls) {
+                Ok(rs) => return Ok(restore_duplicate_names(rs.columns)),
+                Err(e) if is_query => return Err(e),
+                // A WITH leading into an INSERT, UPDATE or DELETE can't be
+                // wrapped
+                Err(_) => (),
+            }
+        }
+
+        if may_run {
+            self.run_for_column_names(sql, nulls)
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    fn run_for_column_names(&self, sql: &str, nulls: &[Value]) -> Result<Vec<String>, sqlx::Error> {
+        // Rolling back doesn't undo last_insert_rowid(), which an INSERT
+        // would leave naming a row that doesn't exist, so we put it back by
+        // inserting that rowid into a scratch table (which the rollback then
+        // drops)
+        let rowid = self.execute_query("SELECT last_insert_rowid()", &[])?;
+        let rowid = rowid.rows.first().and_then(|r| integer(&r.values, 0)).unwrap_or(0);
+
+        self.execute_query("SAVEPOINT sqlx_describe", &[])?;
+        let result = self.execute_query(sql, nulls);
+        let restore = self.execute_query("CREATE TEMP TABLE sqlx_describe_rowid (x)", &[])
+            .and_then(|_| self.execute_query("INSERT INTO sqlx_describe_rowid (rowid) VALUES (?)", &[Value::Integer(rowid)]));
+        let rollback = self.execute_query("ROLLBACK TO sqlx_describe", &[])
+            .and_then(|_| self.execute_query("RELEASE sqlx_describe", &[]));
+        let columns = result?.columns;
+        restore?;
+        rollback?;
+        Ok(columns)
+    }
+
+    fn resolve_origin(&self, origin: &Origin, cursors: &HashMap<i64, Cursor>) -> Result<(SpinSqliteTypeInfo, Option<bool>), sqlx::Error> {
+        let (cursor, column) = match origin {
+            Origin::Constant { type_info, nullable } => return Ok((type_info.clone(), Some(*nullable))),
+            Origin::Cast { type_info, from } => {
+                let nullable = match from {
+                    Some(from) => self.resolve_origin(from, cursors)?.1,
+                    None => None,
+                };
+                return Ok((type_info.clone(), nullable));
+            },
+            Origin::Record(_) => return Ok((SpinSqliteTypeInfo::Null, None)),
+            Origin::Rowid { cursor } => (*cursor, None),
+            Origin::Column { cursor, column } => (*cursor, Some(*column)),
+        };
+        let Some(cursor) = cursors.get(&cursor) else {
+            return Ok((SpinSqliteTypeInfo::Null, None));
+        };
+
+        let schema_column = match column {
+            None => Some(SchemaColumn { type_info: SpinSqliteTypeInfo::Int, not_null: true }),
+            Some(column) => self.schema_column(cursor.root_page, column)?,
+        };
+
+        Ok(match schema_column {
+            Some(c) => (c.type_info, Some(cursor.null_row || !c.not_null)),
+            None => (SpinSqliteTypeInfo::Null, None),
+        })
+    }
+
+    // Finds the table column behind column `column` of the table or index
+    // stored at `root_page`
+    fn schema_column(&self, root_page: i64, column: i64) -> Result<Option<SchemaColumn>, sqlx::Error> {
+        let objects = self.execute_query(
+            "SELECT type, name, tbl_name FROM sqlite_master WHERE rootpage = ?",
+            &[Value::Integer(root_page)],
+        )?;
+        let Some(object) = objects.rows.first() else {
+            return Ok(None);
+        };
+        let (Some(kind), Some(name), Some(table)) = (text(&object.values, 0), text(&object.values, 1), text(&object.values, 2)) else {
+            return Ok(None);
+        };
+
+        let table_column = match kind {
+            "table" => column,
+            "index" => {
+                let index_columns = self.execute_query(
+                    "SELECT cid FROM pragma_index_info(?) WHERE seqno = ?",
+                    &[Value::Text(name.to_owned()), Value::Integer(column)],
+                )?;
+                match index_columns.rows.first().and_then(|r| integer(&r.values, 0)) {
+                    Some(cid) if cid >= 0 => cid,
+                    // The rowid, which indexes carry after their own columns
+                    Some(-1) | None => return Ok(Some(SchemaColumn { type_info: SpinSqliteTypeInfo::Int, not_null: true })),
+                    // An expression, as in an index on lower(name)
+                    Some(_) => return Ok(None),
+                }
+            },
+            _ => return Ok(None),
+        };
+
+        let table_columns = self.execute_query(
+            "SELECT type, \"notnull\" FROM pragma_table_info(?) WHERE cid = ?",
+            &[Value::Text(table.to_owned()), Value::Integer(table_column)],
+        )?;
+        Ok(table_columns.rows.first().map(|r| {
+            let declared_type = text(&r.values, 0).unwrap_or_default();
+            let not_null = integer(&r.values, 1).unwrap_or(0) != 0;
+            SchemaColumn {
+                type_info: SpinSqliteTypeInfo::from_declared_type(declared_type),
+                not_null,
+            }
+        }))
+    }
+}
+
+// Selecting from a subquery makes its column names unique by adding ":1",
+// ":2" etc. to repeats, so `SELECT id, id` comes back as `id, id:1`.  This
+// undoes that, so the names are the ones the statement itself returns.  (A
+// column really named like `id:1` after an `id` comes back as `id` too.)
+fn restore_duplicate_names(names: Vec<String>) -> Vec<String> {
+    let mut restored: Vec<String> = Vec::with_capacity(names.len());
+    for name in names {
+        let base = match name.rsplit_once(':') {
+            Some((base, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => base,
+            _ => &name,
+        };
+        let name = if base != name && restored.iter().any(|r| r == base) { base.to_owned() } else { name };
+        restored.push(name);
+    }
+    restored
+}
+
+impl SpinSqliteTypeInfo {
+    // Per the affinity rules at https://www.sqlite.org/datatype3.html.  NUMERIC
+    // affinity is usually used for things like BOOLEAN, which we store as
+    // integers, so that's what we call it.
+    fn from_declared_type(declared_type: &str) -> Self {
+        let declared_type = declared_type.to_ascii_uppercase();
+        if declared_type.contains("INT") {
+            Self::Int
+        } else if declared_type.contains("CHAR") || declared_type.contains("CLOB") || declared_type.contains("TEXT") {
+            Self::Text
+        } else if declared_type.contains("BLOB") || declared_type.is_empty() {
+            Self::Blob
+        } else if declared_type.contains("REAL") || declared_type.contains("FLOA") || declared_type.contains("DOUB") {
+            Self::Real
+        } else {
+            Self::Int
+        }
+    }
+}
+
+impl Op {
+    // EXPLAIN returns addr, opcode, p1, p2, p3, p4, p5, comment
+    fn from_values(values: &[Value]) -> Option<Self> {
+        Some(Self {
+            opcode: text(values, 1)?.to_owned(),
+            p1: integer(values, 2)?,
+            p2: integer(values, 3)?,
+            p3: integer(values, 4)?,
+            p4: text(values, 5).map(|s| s.to_owned()),
+        })
+    }
+}
+
+fn cursors(program: &[Op]) -> HashMap<i64, Cursor> {
+    let mut cursors: HashMap<i64, Cursor> = HashMap::new();
+    for op in program {
+        match op.opcode.as_str() {
+            "OpenRead" | "OpenWrite" => cursors.entry(op.p1).or_default().root_page = op.p2,
+            "NullRow" => cursors.entry(op.p1).or_default().null_row = true,
+            _ => (),
+        }
+    }
+    cursors
+}
+
+// Walks the program in address order, tracking what's in each register, and
+// returns the origin of each column of the first ResultRow.  This ignores
+// jumps, which is good enough for the straight-line loads that feed a result
+// row.  If a statement has several ResultRows (e.g. a UNION), columns on
+// which they disagree are left unknown.
+//
+// With ORDER BY, rows go through a sorter: MakeRecord packs the registers
+// into a record, SorterInsert stores it, SorterData reads it back into a
+// register, and Column reads its fields through a pseudo-cursor opened on
+// that register.  The loop that fills the sorter comes first in the
+// program, so by the time it's read back we know what's in it.
+fn trace_result_columns(program: &[Op], column_count: usize) -> Vec<Option<Origin>> {
+    let mut registers: HashMap<i64, Origin> = HashMap::new();
+    let mut result: Option<Vec<Option<Origin>>> = None;
+    // Sorter cursor to the record it was last given
+    let mut sorters: HashMap<i64, Vec<Option<Origin>>> = HashMap::new();
+    // Pseudo-cursor to the register holding its record
+    let mut pseudo_cursors: HashMap<i64, i64> = HashMap::new();
+
+    let constant = |type_info, nullable| Origin::Constant { type_info, nullable };
+
+    for op in program {
+        match op.opcode.as_str() {
+            "Column" => {
+                let origin = match pseudo_cursors.get(&op.p1) {
+                    Some(reg) => match registers.get(reg) {
+                        Some(Origin::Record(fields)) => fields.get(op.p2 as usize).cloned().flatten(),
+                        _ => None,
+                    },
+                    None => Some(Origin::Column { cursor: op.p1, column: op.p2 }),
+                };
+                match origin {
+                    Some(origin) => registers.insert(op.p3, origin),
+                    None => registers.remove(&op.p3),
+                };
+            },
+            "MakeRecord" => {
+                let fields = (op.p1..op.p1 + op.p2).map(|reg| registers.get(&reg).cloned()).collect();
+                registers.insert(op.p3, Origin::Record(fields));
+            },
+            "SorterInsert" => {
+                match registers.get(&op.p2) {
+                    Some(Origin::Record(fields)) => sorters.insert(op.p1, fields.clone()),
+                    _ => sorters.remove(&op.p1),
+                };
+            },
+            "SorterData" => {
+                match sorters.get(&op.p1) {
+                    Some(fields) => registers.insert(op.p2, Origin::Record(fields.clone())),
+                    None => registers.remove(&op.p2),
+                };
+            },
+            "OpenPseudo" => { pseudo_cursors.insert(op.p1, op.p2); },
+            "Rowid" | "IdxRowid" => { registers.insert(op.p2, Origin::Rowid { cursor: op.p1 }); },
+            "Integer" | "Int64" => { registers.insert(op.p2, constant(SpinSqliteTypeInfo::Int, false)); },
+            "Real" => { registers.insert(op.p2, constant(SpinSqliteTypeInfo::Real, false)); },
+            "String" | "String8" => { registers.insert(op.p2, constant(SpinSqliteTypeInfo::Text, false)); },
+            "Blob" => { registers.insert(op.p2, constant(SpinSqliteTypeInfo::Blob, false)); },
+            "Count" => { registers.insert(op.p2, constant(SpinSqliteTypeInfo::Int, false)); },
+            "Null" => {
+                // Null sets P2 through P3, or just P2 if P3 is smaller
+                for reg in op.p2..=op.p3.max(op.p2) {
+                    registers.insert(reg, constant(SpinSqliteTypeInfo::Null, true));
+                }
+            },
+            "Copy" | "SCopy" | "IntCopy" => {
+                // Copy carries P3 + 1 registers; the others just the one
+                let count = if op.opcode == "Copy" { op.p3 + 1 } else { 1 };
+                copy_registers(&mut registers, op.p1, op.p2, count);
+            },
+            "Move" => copy_registers(&mut registers, op.p1, op.p2, op.p3),
+            "AggFinal" => {
+                match op.p4.as_deref() {
+                    Some(f) if f.starts_with("count(") => { registers.insert(op.p1, constant(SpinSqliteTypeInfo::Int, false)); },
+                    _ => { registers.remove(&op.p1); },
+                }
+            },
+            "Function" | "PureFunc" | "Add" | "Subtract" | "Multiply" | "Divide" | "Remainder" | "Concat" => {
+                registers.remove(&op.p3);
+            },
+            // Converts P1 in place to the affinity in P2
+            "Cast" => {
+                let from = registers.remove(&op.p1).map(Box::new);
+                if let Some(type_info) = affinity_type(op.p2) {
+                    registers.insert(op.p1, Origin::Cast { type_info, from });
+                }
+            },
+            "ResultRow" => {
+                let row: Vec<Option<Origin>> = (op.p1..op.p1 + op.p2).map(|reg| registers.get(&reg).cloned()).collect();
+                result = Some(match result {
+                    None => row,
+                    Some(previous) => previous.into_iter()
+                        .zip(row)
+                        .map(|(a, b)| if a == b { a } else { None })
+                        .collect(),
+                });
+            },
+            _ => (),
+        }
+    }
+
+    let mut result = result.unwrap_or_default();
+    result.resize(column_count, None);
+    result
+}
+
+// Affinities are the characters 'A' to 'E', per sqliteInt.h
+fn affinity_type(affinity: i64) -> Option<SpinSqliteTypeInfo> {
+    match u8::try_from(affinity).ok()? {
+        b'A' => Some(SpinSqliteTypeInfo::Blob),
+        b'B' => Some(SpinSqliteTypeInfo::Text),
+        // NUMERIC, which we call Int as with declared types
+        b'C' | b'D' => Some(SpinSqliteTypeInfo::Int),
+        b'E' => Some(SpinSqliteTypeInfo::Real),
+        _ => None,
+    }
+}
+
+fn copy_registers(registers: &mut HashMap<i64, Origin>, from: i64, to: i64, count: i64) {
+    for offset in 0..count {
+        match registers.get(&(from + offset)).cloned() {
+            Some(origin) => registers.insert(to + offset, origin),
+            None => registers.remove(&(to + offset)),
+        };
+    }
+}
+
+fn text(values: &[Value], index: usize) -> Option<&str> {
+    match values.get(index) {
+        Some(Value::Text(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn integer(values: &[Value], index: usize) -> Option<i64> {
+    match values.get(index) {
+        Some(Value::Integer(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(opcode: &str, p1: i64, p2: i64, p3: i64) -> Op {
+        Op { opcode: opcode.to_owned(), p1, p2, p3, p4: None }
+    }
+
+    #[test]
+    fn restores_names_a_subquery_made_unique() {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(restore_duplicate_names(names(&["id", "id:1", "name", "id:2"])), names(&["id", "id", "name", "id"]));
+        assert_eq!(restore_duplicate_names(names(&["a:1", "b:", "c:x"])), names(&["a:1", "b:", "c:x"]));
+    }
+
+    #[test]
+    fn cast_retypes_its_register() {
+        // SELECT CAST(age AS TEXT) FROM pets
+        let program = vec![
+            op("OpenRead", 0, 2, 0),
+            op("Column", 0, 1, 1),
+            op("Cast", 1, b'B' as i64, 0),
+            op("ResultRow", 1, 1, 0),
+        ];
+
+        let origins = trace_result_columns(&program, 1);
+
+        assert_eq!(origins, vec![Some(Origin::Cast {
+            type_info: SpinSqliteTypeInfo::Text,
+            from: Some(Box::new(Origin::Column { cursor: 0, column: 1 })),
+        })]);
+    }
+
+    #[test]
+    fn follows_columns_through_a_sorter() {
+        // SELECT name, age FROM pets ORDER BY age
+        let program = vec![
+            op("SorterOpen", 1, 3, 0),
+            op("OpenPseudo", 2, 5, 3),
+            op("OpenRead", 0, 2, 0),
+            op("Column", 0, 2, 6),
+            op("Column", 0, 1, 7),
+            op("MakeRecord", 6, 2, 8),
+            op("SorterInsert", 1, 8, 0),
+            op("SorterSort", 1, 0, 0),
+            op("SorterData", 1, 5, 2),
+            op("Column", 2, 1, 3),
+            op("Column", 2, 0, 4),
+            op("ResultRow", 3, 2, 0),
+        ];
+
+        let origins = trace_result_columns(&program, 2);
+
+        assert_eq!(origins, vec![
+            Some(Origin::Column { cursor: 0, column: 1 }),
+            Some(Origin::Column { cursor: 0, column: 2 }),
+        ]);
+    }
+
+    #[test]
+    fn unknown_sorter_fields_stay_unknown() {
+        let program = vec![
+            op("OpenPseudo", 2, 5, 1),
+            op("SorterData", 1, 5, 2),
+            op("Column", 2, 0, 3),
+            op("ResultRow", 3, 1, 0),
+        ];
+
+        assert_eq!(trace_result_columns(&program, 1), vec![None]);
+    }
+}
@@ -0,0 +1,276 @@
+use std::borrow::Cow;
+use std::fmt::Display;
+
+use sqlx::error::ErrorKind;
+
+use super::SpinSqliteTypeInfo;
+
+// The host doesn't give us SQLite's result codes, only the message text (via
+// rusqlite), so we recover the extended code from that.  Codes are per
+// https://www.sqlite.org/rescode.html, and are reported as decimal strings
+// the same as the upstream sqlx SQLite driver does.
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+const SQLITE_FULL: i32 = 13;
+const SQLITE_CONSTRAINT_CHECK: i32 = 275;
+const SQLITE_CONSTRAINT_FOREIGNKEY: i32 = 787;
+const SQLITE_CONSTRAINT_NOTNULL: i32 = 1299;
+const SQLITE_CONSTRAINT_UNIQUE: i32 = 2067;
+
+/// An error returned from the Spin SQLite host.
+#[derive(Debug)]
+pub struct SpinSqliteDatabaseError {
+    message: String,
+    code: Option<i32>,
+}
+
+impl SpinSqliteDatabaseError {
+    /// Whether the database was busy or locked, meaning the operation may
+    /// succeed if tried again.
+    pub fn is_busy(&self) -> bool {
+        matches!(self.code, Some(SQLITE_BUSY) | Some(SQLITE_LOCKED))
+    }
+}
+
+impl From<spin_sdk::sqlite::Error> for SpinSqliteDatabaseError {
+    fn from(e: spin_sdk::sqlite::Error) -> Self {
+        let (message, code) = match e {
+            spin_sdk::sqlite::Error::Io(message) => {
+                let code = code_from_message(&message);
+                (message, code)
+            },
+            spin_sdk::sqlite::Error::DatabaseFull => ("database or disk is full".to_owned(), Some(SQLITE_FULL)),
+            spin_sdk::sqlite::Error::NoSuchDatabase => ("no such database".to_owned(), None),
+            spin_sdk::sqlite::Error::AccessDenied => ("access to database denied".to_owned(), None),
+            spin_sdk::sqlite::Error::InvalidConnection => ("invalid connection".to_owned(), None),
+        };
+        Self { message, code }
+    }
+}
+
+fn code_from_message(message: &str) -> Option<i32> {
+    // A primary key clash also reports as "UNIQUE constraint failed"
+    if message.contains("UNIQUE constraint failed") {
+        Some(SQLITE_CONSTRAINT_UNIQUE)
+    } else if message.contains("FOREIGN KEY constraint failed") {
+        Some(SQLITE_CONSTRAINT_FOREIGNKEY)
+    } else if message.contains("NOT NULL constraint failed") {
+        Some(SQLITE_CONSTRAINT_NOTNULL)
+    } else if message.contains("CHECK constraint failed") {
+        Some(SQLITE_CONSTRAINT_CHECK)
+    } else if message.contains("database table is locked") {
+        Some(SQLITE_LOCKED)
+    } else if message.contains("database is locked") || message.contains("database is busy") {
+        Some(SQLITE_BUSY)
+    } else {
+        None
+    }
+}
+
+impl std::error::Error for SpinSqliteDatabaseError {}
+
+impl Display for SpinSqliteDatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "(code: {code}) {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl sqlx::error::DatabaseError for SpinSqliteDatabaseError {
+    fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        self.code.map(|code| Cow::Owned(code.to_string()))
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self.code {
+            Some(SQLITE_CONSTRAINT_UNIQUE) => ErrorKind::UniqueViolation,
+            Some(SQLITE_CONSTRAINT_FOREIGNKEY) => ErrorKind::ForeignKeyViolation,
+            Some(SQLITE_CONSTRAINT_NOTNULL) => ErrorKind::NotNullViolation,
+            Some(SQLITE_CONSTRAINT_CHECK) => ErrorKind::CheckViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// A value that couldn't be decoded as the Rust type asked for.
+#[derive(Debug)]
+pub struct SpinSqliteDecodeError {
+    expected: String,
+    actual: SpinSqliteTypeInfo,
+    value: String,
+    reason: DecodeFailure,
+}
+
+#[derive(Debug)]
+enum DecodeFailure {
+    WrongType,
+    OutOfRange,
+    Inexact,
+}
+
+impl SpinSqliteDecodeError {
+    pub(super) fn wrong_type(expected: &str, value: &spin_sdk::sqlite::Value) -> Self {
+        Self::new(expected, value, DecodeFailure::WrongType)
+    }
+
+    pub(super) fn out_of_range(expected: &str, value: &spin_sdk::sqlite::Value) -> Self {
+        Self::new(expected, value, DecodeFailure::OutOfRange)
+    }
+
+    pub(super) fn inexact(expected: &str, value: &spin_sdk::sqlite::Value) -> Self {
+        Self::new(expected, value, DecodeFailure::Inexact)
+    }
+
+    fn new(expected: &str, value: &spin_sdk::sqlite::Value, reason: DecodeFailure) -> Self {
+        Self {
+            expected: short_type_name(expected),
+            actual: SpinSqliteTypeInfo::from(value),
+            value: summarize(value),
+            reason,
+        }
+    }
+
+    /// The Rust type the value was to be decoded as.
+    pub fn expected_type(&self) -> &str {
+        &self.expected
+    }
+
+    /// The SQLite type of the value.
+    pub fn actual_type(&self) -> &SpinSqliteTypeInfo {
+        &self.actual
+    }
+
+    /// The value, or for long text and blobs a summary of it.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl std::error::Error for SpinSqliteDecodeError {}
+
+impl Display for SpinSqliteDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self { expected, actual, value, reason } = self;
+        match reason {
+            DecodeFailure::WrongType => write!(f, "expected {expected}, found {actual} {value}"),
+            DecodeFailure::OutOfRange => write!(f, "{actual} {value} is out of range for {expected}"),
+            DecodeFailure::Inexact => write!(f, "{actual} {value} can't be represented exactly as {expected}"),
+        }
+    }
+}
+
+const MAX_SUMMARY_CHARS: usize = 40;
+
+fn summarize(value: &spin_sdk::sqlite::Value) -> String {
+    match value {
+        spin_sdk::sqlite::Value::Integer(n) => n.to_string(),
+        spin_sdk::sqlite::Value::Real(n) => format!("{n:?}"),
+        spin_sdk::sqlite::Value::Text(s) if s.chars().count() > MAX_SUMMARY_CHARS => {
+            let start: String = s.chars().take(MAX_SUMMARY_CHARS).collect();
+            format!("{start:?}...")
+        },
+        spin_sdk::sqlite::Value::Text(s) => format!("{s:?}"),
+        spin_sdk::sqlite::Value::Blob(b) => format!("<{} bytes>", b.len()),
+        spin_sdk::sqlite::Value::Null => "NULL".to_owned(),
+    }
+}
+
+// std::any::type_name gives full paths (alloc::string::String), which are
+// noise in an error message
+fn short_type_name(name: &str) -> String {
+    name.split_inclusive(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .map(|part| part.rsplit("::").next().unwrap_or(part))
+        .collect()
+}
+
+/// A column was looked up by a name which the query returned more than once,
+/// as with `SELECT * FROM a JOIN b` where both tables have an `id`.  Alias
+/// the columns apart, or look them up by index.
+#[derive(Debug)]
+pub struct SpinSqliteAmbiguousColumnError {
+    name: String,
+    ordinals: Vec<usize>,
+}
+
+impl SpinSqliteAmbiguousColumnError {
+    pub(super) fn new(name: &str, ordinals: Vec<usize>) -> Self {
+        Self { name: name.to_owned(), ordinals }
+    }
+
+    /// The name that was looked up.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The indexes of the columns that matched it.
+    pub fn ordinals(&self) -> &[usize] {
+        &self.ordinals
+    }
+}
+
+impl std::error::Error for SpinSqliteAmbiguousColumnError {}
+
+impl Display for SpinSqliteAmbiguousColumnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "column name {:?} is ambiguous: it matches the columns at indexes {:?}", self.name, self.ordinals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::error::{DatabaseError, ErrorKind};
+
+    use super::SpinSqliteDatabaseError;
+
+    fn from_message(message: &str) -> SpinSqliteDatabaseError {
+        spin_sdk::sqlite::Error::Io(message.to_owned()).into()
+    }
+
+    #[test]
+    fn recognises_host_messages() {
+        // Messages as rusqlite gives them to the host
+        let cases = [
+            ("UNIQUE constraint failed: pets.name", ErrorKind::UniqueViolation, false),
+            ("UNIQUE constraint failed: pets.id", ErrorKind::UniqueViolation, false),
+            ("FOREIGN KEY constraint failed", ErrorKind::ForeignKeyViolation, false),
+            ("NOT NULL constraint failed: pets.name", ErrorKind::NotNullViolation, false),
+            ("CHECK constraint failed: age > 0", ErrorKind::CheckViolation, false),
+            ("database is locked", ErrorKind::Other, true),
+            ("database table is locked: pets", ErrorKind::Other, true),
+            ("no such table: pets", ErrorKind::Other, false),
+        ];
+
+        for (message, kind, busy) in cases {
+            let error = from_message(message);
+            assert_eq!(error.kind(), kind, "{message}");
+            assert_eq!(error.is_busy(), busy, "{message}");
+            assert_eq!(error.message(), message);
+        }
+    }
+
+    #[test]
+    fn reports_codes_as_upstream_does() {
+        assert_eq!(from_message("UNIQUE constraint failed: pets.id").code().as_deref(), Some("2067"));
+        assert_eq!(from_message("database is locked").code().as_deref(), Some("5"));
+        assert_eq!(from_message("database table is locked").code().as_deref(), Some("6"));
+        assert_eq!(from_message("no such table: pets").code(), None);
+    }
+}
@@ -0,0 +1,548 @@
+use std::sync::Mutex;
+
+use spin_sdk::sqlite::{QueryResult, RowResult, Value};
+
+use sqlx::ConnectOptions as _;
+
+use super::{SqlxConnection, SqlxConnectionOptions};
+
+type Response = Box<dyn Fn() -> QueryResult + Send>;
+
+// Stands in for the host connection, recording each statement it's asked to
+// run.  Statements get the result they were given with `respond`, if any;
+// otherwise the change count queries get zeros and everything else gets no
+// rows.  Statements given to `fail_once` fail the next time they're run.
+//
+// pub only so it can stand in for the host connection in pub signatures;
+// this module is private
+#[derive(Default)]
+pub struct FakeHost {
+    executed: Mutex<Vec<String>>,
+    responses: Mutex<Vec<(String, Response)>>,
+    failures: Mutex<Vec<(String, String)>>,
+}
+
+impl std::fmt::Debug for FakeHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FakeHost").field("executed", &self.executed).finish_non_exhaustive()
+    }
+}
+
+impl FakeHost {
+    pub(super) fn open(_label: &str) -> Result<Self, spin_sdk::sqlite::Error> {
+        Ok(Self::default())
+    }
+
+    pub(super) fn open_default() -> Result<Self, spin_sdk::sqlite::Error> {
+        Ok(Self::default())
+    }
+
+    pub(super) fn execute(&self, sql: &str, _args: &[Value]) -> Result<QueryResult, spin_sdk::sqlite::Error> {
+        self.executed.lock().unwrap().push(sql.to_owned());
+        let mut failures = self.failures.lock().unwrap();
+        if let Some(i) = failures.iter().position(|(s, _)| s == sql) {
+            let (_, message) = failures.remove(i);
+            return Err(spin_sdk::sqlite::Error::Io(message));
+        }
+        drop(failures);
+        if let Some((_, response)) = self.responses.lock().unwrap().iter().find(|(s, _)| s == sql) {
+            return Ok(response());
+        }
+        let zeros = match sql {
+            "SELECT total_changes()" => 1,
+            "SELECT changes(), total_changes(), last_insert_rowid()" => 3,
+            _ => 0,
+        };
+        let rows = if zeros > 0 {
+            vec![RowResult { values: vec![Value::Integer(0); zeros] }]
+        } else {
+            vec![]
+        };
+        Ok(QueryResult { columns: vec![], rows })
+    }
+
+    fn respond(&self, sql: &str, response: impl Fn() -> QueryResult + Send + 'static) {
+        self.responses.lock().unwrap().push((sql.to_owned(), Box::new(response)));
+    }
+
+    fn fail_once(&self, sql: &str, message: &str) {
+        self.failures.lock().unwrap().push((sql.to_owned(), message.to_owned()));
+    }
+
+    fn executed(&self) -> Vec<String> {
+        self.executed.lock().unwrap().clone()
+    }
+}
+
+fn connection() -> SqlxConnection {
+    SqlxConnection::new(FakeHost::default())
+}
+
+#[test]
+fn unpolled_queries_do_nothing() {
+    let conn = connection();
+
+    drop(sqlx::Executor::fetch_many(&conn, sqlx::query::<SqlxConnection>("DELETE FROM pets")));
+    drop(sqlx::Executor::fetch_optional(&conn, sqlx::query::<SqlxConnection>("DELETE FROM pets")));
+    drop(sqlx::Executor::execute(&conn, sqlx::query::<SqlxConnection>("DELETE FROM pets")));
+    drop(sqlx::query::<SqlxConnection>("DELETE FROM pets").fetch_all(&conn));
+
+    assert!(conn.inner.executed().is_empty());
+}
+
+#[test]
+fn polled_queries_run() {
+    let conn = connection();
+
+    futures::executor::block_on(sqlx::Executor::execute(&conn, sqlx::query::<SqlxConnection>("DELETE FROM pets"))).unwrap();
+    futures::executor::block_on(sqlx::Executor::fetch_optional(&conn, sqlx::query::<SqlxConnection>("SELECT 1"))).unwrap();
+
+    let executed = conn.inner.executed();
+    assert!(executed.iter().any(|sql| sql == "DELETE FROM pets"));
+    assert!(executed.iter().any(|sql| sql == "SELECT 1"));
+}
+
+#[test]
+fn unpolled_begin_with_leaves_mode_alone() {
+    let mut conn = connection();
+
+    drop(conn.begin_with(super::TransactionMode::Exclusive));
+    let tx = futures::executor::block_on(sqlx::Connection::begin(&mut conn)).unwrap();
+    drop(tx);
+
+    assert_eq!(conn.inner.executed().first().map(String::as_str), Some("BEGIN DEFERRED"));
+}
+
+#[test]
+fn begin_with_rejects_nested_transactions() {
+    let mut conn = connection();
+
+    let mut tx = futures::executor::block_on(conn.begin_with(super::TransactionMode::Immediate)).unwrap();
+    let nested = futures::executor::block_on(tx.begin_with(super::TransactionMode::Exclusive));
+
+    assert!(matches!(nested, Err(sqlx::Error::Configuration(_))));
+}
+
+#[test]
+fn nested_transactions_use_savepoints() {
+    let mut conn = connection();
+
+    futures::executor::block_on(async {
+        let mut outer = sqlx::Connection::begin(&mut conn).await.unwrap();
+        let mut middle = sqlx::Connection::begin(&mut *outer).await.unwrap();
+        let inner = sqlx::Connection::begin(&mut *middle).await.unwrap();
+        inner.commit().await.unwrap();
+        middle.rollback().await.unwrap();
+        outer.commit().await.unwrap();
+    });
+
+    assert_eq!(conn.inner.executed(), vec![
+        "BEGIN DEFERRED",
+        "SAVEPOINT sqlx_savepoint_1",
+        "SAVEPOINT sqlx_savepoint_2",
+        "RELEASE sqlx_savepoint_2",
+        "ROLLBACK TO sqlx_savepoint_1",
+        "COMMIT",
+    ]);
+    assert_eq!(conn.transaction_depth, 0);
+}
+
+#[test]
+fn dropped_transactions_roll_back() {
+    let mut conn = connection();
+
+    futures::executor::block_on(async {
+        let mut outer = sqlx::Connection::begin(&mut conn).await.unwrap();
+        let nested = sqlx::Connection::begin(&mut *outer).await.unwrap();
+        drop(nested);
+        drop(outer);
+    });
+
+    assert_eq!(conn.inner.executed(), vec![
+        "BEGIN DEFERRED",
+        "SAVEPOINT sqlx_savepoint_1",
+        "ROLLBACK TO sqlx_savepoint_1",
+        "ROLLBACK",
+    ]);
+    assert_eq!(conn.transaction_depth, 0);
+}
+
+fn busy_error() -> sqlx::Error {
+    super::SpinSqliteDatabaseError::from(spin_sdk::sqlite::Error::Io("database is locked".to_owned())).into()
+}
+
+#[test]
+fn busy_transactions_are_retried() {
+    let mut conn = connection();
+    conn.set_busy_retries(2);
+    // Failing to roll back mustn't hide that the transaction was busy
+    conn.inner.fail_once("ROLLBACK", "disk I/O error");
+    let attempts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    let callback_attempts = attempts.clone();
+    let result = futures::executor::block_on(conn.transaction_with_retry(move |_tx| {
+        let attempts = callback_attempts.clone();
+        Box::pin(async move {
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed) {
+                0 | 1 => Err(busy_error()),
+                n => Ok(n),
+            }
+        })
+    }));
+
+    assert_eq!(result.unwrap(), 2);
+    assert_eq!(attempts.load(std::sync::atomic::Ordering::Relaxed), 3);
+    assert_eq!(conn.inner.executed(), vec![
+        "BEGIN DEFERRED",
+        "ROLLBACK",
+        // The failed rollback is tried again as the transaction is dropped
+        "ROLLBACK",
+        "BEGIN DEFERRED",
+        "ROLLBACK",
+        "BEGIN DEFERRED",
+        "COMMIT",
+    ]);
+}
+
+#[test]
+fn busy_retries_run_out() {
+    let mut conn = connection();
+    conn.set_busy_retries(1);
+
+    let result: Result<(), _> = futures::executor::block_on(conn.transaction_with_retry(|_tx| Box::pin(async { Err(busy_error()) })));
+
+    assert!(super::is_busy(&result.unwrap_err()));
+    assert_eq!(conn.inner.executed().iter().filter(|s| *s == "BEGIN DEFERRED").count(), 2);
+}
+
+#[test]
+fn queries_skip_change_counts() {
+    let conn = connection();
+
+    futures::executor::block_on(sqlx::query::<SqlxConnection>("SELECT name FROM pets").fetch_all(&conn)).unwrap();
+
+    assert_eq!(conn.inner.executed(), vec!["SELECT name FROM pets"]);
+}
+
+#[test]
+fn modifications_count_changes() {
+    let conn = connection();
+
+    futures::executor::block_on(sqlx::query::<SqlxConnection>("DELETE FROM pets").execute(&conn)).unwrap();
+
+    assert_eq!(conn.inner.executed().len(), 3);
+}
+
+#[test]
+fn counts_positional_parameters() {
+    assert_eq!(super::count_parameters("SELECT 1"), 0);
+    assert_eq!(super::count_parameters("SELECT * FROM pets WHERE age < ? AND name = ?"), 2);
+    assert_eq!(super::count_parameters("SELECT ?3"), 3);
+    assert_eq!(super::count_parameters("SELECT ?2, ?1, ?2"), 2);
+    assert_eq!(super::count_parameters("SELECT ?5, ?"), 6);
+}
+
+#[test]
+fn counts_named_parameters_once_each() {
+    assert_eq!(super::count_parameters("SELECT :a, @b, $c"), 3);
+    assert_eq!(super::count_parameters("SELECT :name WHERE x = :name OR y = :other"), 2);
+    assert_eq!(super::count_parameters("SELECT ?, :a"), 2);
+}
+
+#[test]
+fn ignores_parameters_in_literals_and_comments() {
+    assert_eq!(super::count_parameters("SELECT '?', \"?\", `?`, [?] FROM t WHERE a = ?"), 1);
+    assert_eq!(super::count_parameters("SELECT 'it''s ?' WHERE a = :a"), 1);
+    assert_eq!(super::count_parameters("SELECT 1 -- ?\nWHERE a = ?"), 1);
+    assert_eq!(super::count_parameters("SELECT /* :a ? */ ?"), 1);
+}
+
+#[test]
+fn ignores_markers_inside_identifiers() {
+    assert_eq!(super::count_parameters("SELECT a$b FROM t"), 0);
+    assert_eq!(super::count_parameters("SELECT x FROM t WHERE y = 'a:b'"), 0);
+}
+
+#[test]
+fn prepare_describes_without_running() {
+    let conn = connection();
+    let sql = "SELECT name FROM pets WHERE age < ?";
+    respond_with_program(&conn, sql, &[("ResultRow", 1, 1, 0)]);
+    respond_with_names(&conn, &format!("SELECT * FROM (\n{sql}\n) LIMIT 0"), &["name"]);
+
+    let statement = futures::executor::block_on(sqlx::Executor::prepare(&conn, sql)).unwrap();
+
+    let names: Vec<&str> = sqlx::Statement::columns(&statement).iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["name"]);
+    assert!(!conn.inner.executed().iter().any(|s| s == sql));
+}
+
+#[test]
+fn prepare_leaves_returning_columns_unknown() {
+    let conn = connection();
+    let sql = "INSERT INTO pets (name) VALUES (?) RETURNING id";
+    respond_with_program(&conn, sql, &[("ResultRow", 1, 1, 0)]);
+
+    let statement = futures::executor::block_on(sqlx::Executor::prepare(&conn, sql)).unwrap();
+
+    assert!(sqlx::Statement::columns(&statement).is_empty());
+    assert_eq!(conn.inner.executed(), vec![format!("EXPLAIN {sql}")]);
+}
+
+// Has EXPLAIN of `sql` show the given (opcode, p1, p2, p3)s
+fn respond_with_program(conn: &SqlxConnection, sql: &str, program: &'static [(&'static str, i64, i64, i64)]) {
+    conn.inner.respond(&format!("EXPLAIN {sql}"), move || QueryResult {
+        columns: vec![],
+        rows: program.iter().enumerate().map(|(addr, (opcode, p1, p2, p3))| RowResult {
+            values: vec![
+                Value::Integer(addr as i64),
+                Value::Text(opcode.to_string()),
+                Value::Integer(*p1),
+                Value::Integer(*p2),
+                Value::Integer(*p3),
+                Value::Null,
+                Value::Integer(0),
+                Value::Null,
+            ],
+        }).collect(),
+    });
+}
+
+fn respond_with_names(conn: &SqlxConnection, sql: &str, names: &'static [&'static str]) {
+    conn.inner.respond(sql, || QueryResult {
+        columns: names.iter().map(|n| n.to_string()).collect(),
+        rows: vec![],
+    });
+}
+
+#[test]
+fn describe_names_queries_without_running_them() {
+    let conn = connection();
+    let sql = "SELECT id, id FROM pets";
+    respond_with_program(&conn, sql, &[("ResultRow", 1, 2, 0)]);
+    respond_with_names(&conn, &format!("SELECT * FROM (\n{sql}\n) LIMIT 0"), &["id", "id:1"]);
+
+    let describe = futures::executor::block_on(sqlx::Executor::describe(&conn, sql)).unwrap();
+
+    let names: Vec<&str> = describe.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["id", "id"]);
+    assert!(!conn.inner.executed().iter().any(|s| s == sql || s.starts_with("SAVEPOINT")));
+}
+
+#[test]
+fn describe_runs_returning_statements_and_undoes_them() {
+    let conn = connection();
+    let sql = "INSERT INTO pets (name) VALUES (?) RETURNING id, id";
+    respond_with_program(&conn, sql, &[("ResultRow", 1, 2, 0)]);
+    respond_with_names(&conn, sql, &["id", "id"]);
+
+    let describe = futures::executor::block_on(sqlx::Executor::describe(&conn, sql)).unwrap();
+
+    let names: Vec<&str> = describe.columns.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["id", "id"]);
+    let executed = conn.inner.executed();
+    let run = executed.iter().position(|s| s == sql).unwrap();
+    assert_eq!(executed[run - 2..run], ["SELECT last_insert_rowid()", "SAVEPOINT sqlx_describe"]);
+    assert_eq!(executed[run + 1..], [
+        "CREATE TEMP TABLE sqlx_describe_rowid (x)",
+        "INSERT INTO sqlx_describe_rowid (rowid) VALUES (?)",
+        "ROLLBACK TO sqlx_describe",
+        "RELEASE sqlx_describe",
+    ]);
+}
+
+// Describes a query reading the first column of an index on pets, which
+// pragma_index_info says is table column `cid`
+fn describe_index_column(cid: i64) -> sqlx::Describe<SqlxConnection> {
+    let conn = connection();
+    let sql = "SELECT k FROM pets INDEXED BY pets_k";
+    respond_with_program(&conn, sql, &[("OpenRead", 1, 3, 0), ("Column", 1, 0, 1), ("ResultRow", 1, 1, 0)]);
+    respond_with_names(&conn, &format!("SELECT * FROM (\n{sql}\n) LIMIT 0"), &["k"]);
+    conn.inner.respond("SELECT type, name, tbl_name FROM sqlite_master WHERE rootpage = ?", || QueryResult {
+        columns: vec![],
+        rows: vec![RowResult {
+            values: vec![Value::Text("index".to_owned()), Value::Text("pets_k".to_owned()), Value::Text("pets".to_owned())],
+        }],
+    });
+    conn.inner.respond("SELECT cid FROM pragma_index_info(?) WHERE seqno = ?", move || QueryResult {
+        columns: vec![],
+        rows: vec![RowResult { values: vec![Value::Integer(cid)] }],
+    });
+
+    futures::executor::block_on(sqlx::Executor::describe(&conn, sql)).unwrap()
+}
+
+#[test]
+fn describes_the_rowid_in_an_index() {
+    let describe = describe_index_column(-1);
+
+    assert_eq!(describe.columns[0].type_info, super::SpinSqliteTypeInfo::Int);
+    assert_eq!(describe.nullable, vec![Some(false)]);
+}
+
+#[test]
+fn leaves_expressions_in_an_index_unknown() {
+    let describe = describe_index_column(-2);
+
+    assert_eq!(describe.columns[0].type_info, super::SpinSqliteTypeInfo::Null);
+    assert_eq!(describe.nullable, vec![None]);
+}
+
+#[test]
+fn urls_round_trip_any_label() {
+    for label in ["default", "Mixed.Case-db_1", "my db", "a/b", "/abs", "café", "x?y#z", "100%", "user:pw@host"] {
+        let options = SqlxConnectionOptions::with_label(label.to_owned()).read_only(true).busy_retries(3);
+        let url = options.to_url_lossy();
+        let parsed = SqlxConnectionOptions::from_url(&url).unwrap();
+        assert_eq!(parsed.label, label, "{url}");
+        assert!(parsed.read_only, "{url}");
+        assert_eq!(parsed.busy_retries, 3, "{url}");
+    }
+}
+
+#[test]
+fn parses_connection_strings() {
+    let options: SqlxConnectionOptions = "spin-sqlite://default".parse().unwrap();
+    assert_eq!(options.label, "default");
+    assert!(!options.read_only);
+
+    let options: SqlxConnectionOptions = "spin-sqlite:other".parse().unwrap();
+    assert_eq!(options.label, "other");
+
+    let options: SqlxConnectionOptions = "plain".parse().unwrap();
+    assert_eq!(options.label, "plain");
+
+    let options: SqlxConnectionOptions = "spin-sqlite://default?mode=ro&foreign_keys=1&busy_retries=5&transaction_mode=immediate&lenient_decoding=true&case_insensitive_column_names=false"
+        .parse()
+        .unwrap();
+    assert!(options.read_only);
+    assert_eq!(options.foreign_keys, Some(true));
+    assert_eq!(options.busy_retries, 5);
+    assert_eq!(options.transaction_mode, super::TransactionMode::Immediate);
+    assert!(options.lenient_decoding);
+    assert!(!options.case_insensitive_column_names);
+}
+
+#[test]
+fn rejects_bad_connection_strings() {
+    for s in [
+        "",
+        "postgres://default",
+        "spin-sqlite://",
+        "spin-sqlite://default/extra",
+        "spin-sqlite://default?cache=shared",
+        "spin-sqlite://default?mode=rwc",
+        "spin-sqlite://default?busy_retries=lots",
+        "spin-sqlite://default?foreign_keys=yes",
+        "spin-sqlite://default?transaction_mode=sometimes",
+    ] {
+        let result = s.parse::<SqlxConnectionOptions>();
+        assert!(matches!(result, Err(sqlx::Error::Configuration(_))), "{s:?} gave {result:?}");
+    }
+}
+
+#[test]
+fn ambiguous_column_names_are_column_errors() {
+    let conn = connection();
+    let sql = "SELECT a.id, b.id FROM a JOIN b";
+    conn.inner.respond(sql, || QueryResult {
+        columns: vec!["id".to_owned(), "id".to_owned()],
+        rows: vec![RowResult { values: vec![Value::Integer(1), Value::Integer(2)] }],
+    });
+
+    let row = futures::executor::block_on(sqlx::query(sql).fetch_one(&conn)).unwrap();
+
+    match sqlx::Row::try_get::<i64, _>(&row, "id") {
+        Err(sqlx::Error::ColumnDecode { index, source }) => {
+            assert_eq!(index, "\"id\"");
+            let source = source.downcast_ref::<super::SpinSqliteAmbiguousColumnError>().unwrap();
+            assert_eq!(source.ordinals(), &[0, 1]);
+        },
+        other => panic!("expected a ColumnDecode error, got {other:?}"),
+    }
+    assert!(matches!(sqlx::Row::try_get::<i64, _>(&row, "name"), Err(sqlx::Error::ColumnNotFound(_))));
+}
+
+// Reads `value` back from the fake host as a T
+fn decode<T>(value: Value, lenient: bool) -> Result<T, sqlx::Error>
+where
+    T: for<'r> sqlx::Decode<'r, super::SqlxConnection> + sqlx::Type<super::SqlxConnection>,
+{
+    let mut conn = connection();
+    conn.set_lenient_decoding(lenient);
+    conn.inner.respond("SELECT v", move || QueryResult {
+        columns: vec!["v".to_owned()],
+        rows: vec![RowResult { values: vec![value.clone()] }],
+    });
+    let row = futures::executor::block_on(sqlx::query("SELECT v").fetch_one(&conn)).unwrap();
+    sqlx::Row::try_get(&row, 0)
+}
+
+fn encode<'q>(value: impl sqlx::Encode<'q, super::SqlxConnection> + sqlx::Type<super::SqlxConnection> + Send + 'q) -> Vec<Value> {
+    let mut args = super::SpinSqliteArgs::default();
+    sqlx::Arguments::add(&mut args, value);
+    args.inner
+}
+
+fn is_decode_error(result: Result<impl std::fmt::Debug, sqlx::Error>) -> bool {
+    matches!(result, Err(sqlx::Error::ColumnDecode { .. }))
+}
+
+#[test]
+fn integers_must_fit_their_type() {
+    assert_eq!(decode::<i8>(Value::Integer(-128), false).unwrap(), -128);
+    assert!(is_decode_error(decode::<i8>(Value::Integer(128), false)));
+    assert!(is_decode_error(decode::<u8>(Value::Integer(-1), false)));
+    assert!(is_decode_error(decode::<u32>(Value::Integer(1 << 32), false)));
+    assert_eq!(decode::<u64>(Value::Integer(i64::MAX), false).unwrap(), i64::MAX as u64);
+}
+
+#[test]
+fn integers_decode_as_floats_only_exactly() {
+    assert_eq!(decode::<f64>(Value::Integer(42), false).unwrap(), 42.0);
+    assert_eq!(decode::<f64>(Value::Integer(1 << 53), false).unwrap(), 9007199254740992.0);
+    assert!(is_decode_error(decode::<f64>(Value::Integer((1 << 53) + 1), false)));
+    assert!(is_decode_error(decode::<f64>(Value::Integer(i64::MAX), false)));
+}
+
+#[test]
+fn reals_round_into_f32() {
+    assert_eq!(decode::<f32>(Value::Real(6.1), false).unwrap(), 6.1_f32);
+    assert_eq!(decode::<f32>(Value::Real(f64::from(f32::MAX)), false).unwrap(), f32::MAX);
+    assert!(is_decode_error(decode::<f32>(Value::Real(1e39), false)));
+    assert!(is_decode_error(decode::<f32>(Value::Real(-1e39), false)));
+}
+
+#[test]
+fn infinities_round_trip() {
+    assert_eq!(encode(f32::INFINITY), vec![Value::Real(f64::INFINITY)]);
+    assert_eq!(decode::<f32>(Value::Real(f64::INFINITY), false).unwrap(), f32::INFINITY);
+    assert_eq!(decode::<f32>(Value::Real(f64::NEG_INFINITY), false).unwrap(), f32::NEG_INFINITY);
+    assert_eq!(decode::<f64>(Value::Real(f64::INFINITY), false).unwrap(), f64::INFINITY);
+}
+
+#[test]
+fn nan_binds_as_null() {
+    assert_eq!(encode(f64::NAN), vec![Value::Null]);
+    assert_eq!(encode(f32::NAN), vec![Value::Null]);
+    assert_eq!(encode(1.5_f64), vec![Value::Real(1.5)]);
+}
+
+#[test]
+fn lenient_decoding_parses_text() {
+    assert_eq!(decode::<i64>(Value::Text("42".to_owned()), true).unwrap(), 42);
+    assert_eq!(decode::<i64>(Value::Text(" 42 ".to_owned()), true).unwrap(), 42);
+    assert_eq!(decode::<f64>(Value::Text("2.5".to_owned()), true).unwrap(), 2.5);
+    assert!(decode::<bool>(Value::Text(" t ".to_owned()), true).unwrap());
+    assert!(!decode::<bool>(Value::Text("FALSE".to_owned()), true).unwrap());
+    assert!(is_decode_error(decode::<i64>(Value::Text("forty-two".to_owned()), true)));
+    assert!(is_decode_error(decode::<bool>(Value::Text("yes".to_owned()), true)));
+    assert!(is_decode_error(decode::<u8>(Value::Text("300".to_owned()), true)));
+}
+
+#[test]
+fn strict_decoding_rejects_text() {
+    assert!(is_decode_error(decode::<i64>(Value::Text("42".to_owned()), false)));
+    assert!(is_decode_error(decode::<f64>(Value::Text("2.5".to_owned()), false)));
+    assert!(is_decode_error(decode::<bool>(Value::Text("t".to_owned()), false)));
+}